@@ -0,0 +1,359 @@
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use eyre::{WrapErr, bail};
+use futures::{StreamExt, stream};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    check::{LinkStatus, classify},
+    dates::Timestamp,
+    fetch::FetchedPage,
+};
+
+/// Availability API of the Wayback Machine
+pub const DEFAULT_ARCHIVE_ENDPOINT: &str =
+    "https://archive.org/wayback/available";
+
+/// Format of the timestamps in Wayback Machine URLs and responses
+const WAYBACK_TIMESTAMP: &str = "%Y%m%d%H%M%S";
+
+/// Response from the availability API
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Availability {
+    archived_snapshots: ArchivedSnapshots,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ArchivedSnapshots {
+    closest: Option<Closest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Closest {
+    #[serde(default)]
+    available: bool,
+    url: String,
+    #[serde(default)]
+    timestamp: Option<String>,
+    /// HTTP status the page was captured with, e.g. `"200"`
+    #[serde(default)]
+    status: Option<String>,
+}
+
+/// Capture of a page held by an archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Where the snapshot can be viewed
+    pub url: Url,
+    /// When the page was captured
+    pub captured: Option<Timestamp>,
+}
+
+impl Snapshot {
+    /// URL of the page exactly as captured, i.e. without the archive's
+    /// toolbar or rewritten links
+    ///
+    /// The Wayback Machine serves this when `id_` follows the timestamp.
+    pub fn raw_url(&self) -> Url {
+        let re = Regex::new(r"/web/(\d+)/").unwrap();
+        let raw = re.replace(self.url.as_str(), "/web/${1}id_/");
+        Url::parse(&raw).unwrap_or_else(|_| self.url.clone())
+    }
+}
+
+/// Page recovered from an archive in place of one we couldn't fetch
+#[derive(Clone, Debug)]
+pub struct ArchivedPage {
+    pub snapshot: Snapshot,
+    pub html: String,
+}
+
+/// Client for a Wayback Machine-compatible archive
+#[derive(Clone, Debug)]
+pub struct Archive {
+    client: reqwest::Client,
+    /// Availability API, queried as `?url=...&timestamp=...`
+    endpoint: Url,
+}
+
+impl Archive {
+    pub fn new(client: reqwest::Client, endpoint: Url) -> Self {
+        Self { client, endpoint }
+    }
+
+    /// Snapshot of `url` captured closest to `near` (or the most recent one,
+    /// if not given), if the archive has one
+    pub async fn closest(
+        &self,
+        url: &Url,
+        near: Option<Timestamp>,
+    ) -> eyre::Result<Option<Snapshot>> {
+        let mut query = self.endpoint.clone();
+        query.query_pairs_mut().append_pair("url", url.as_str());
+        if let Some(near) = near {
+            query.query_pairs_mut().append_pair(
+                "timestamp",
+                &near
+                    .with_timezone(&Utc)
+                    .format(WAYBACK_TIMESTAMP)
+                    .to_string(),
+            );
+        }
+
+        let res = self
+            .client
+            .get(query.clone())
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to query {query}"))?;
+        if !res.status().is_success() {
+            bail!("{query} returned {}", res.status());
+        }
+        let availability: Availability = res
+            .json()
+            .await
+            .wrap_err_with(|| format!("invalid response from {query}"))?;
+
+        let Some(closest) = availability.archived_snapshots.closest else {
+            return Ok(None);
+        };
+        /* a capture of an error page is no better than the live one */
+        if !closest.available
+            || closest.status.as_ref().is_some_and(|s| !s.starts_with('2'))
+        {
+            debug!("no usable snapshot of {url}: {closest:?}");
+            return Ok(None);
+        }
+
+        let captured = closest.timestamp.as_deref().and_then(|t| {
+            NaiveDateTime::parse_from_str(t, WAYBACK_TIMESTAMP)
+                .ok()
+                .map(|t| Utc.from_utc_datetime(&t).fixed_offset())
+        });
+        let url = Url::parse(&closest.url).wrap_err_with(|| {
+            format!("invalid snapshot URL `{}`", closest.url)
+        })?;
+
+        Ok(Some(Snapshot { url, captured }))
+    }
+
+    /// Retrieve the snapshot of `url` closest to `near`, if there is one
+    pub async fn retrieve(
+        &self,
+        url: &Url,
+        near: Option<Timestamp>,
+    ) -> eyre::Result<Option<ArchivedPage>> {
+        let Some(snapshot) = self.closest(url, near).await? else {
+            return Ok(None);
+        };
+
+        let raw = snapshot.raw_url();
+        let res = self
+            .client
+            .get(raw.clone())
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to fetch {raw}"))?;
+        if !res.status().is_success() {
+            bail!("{raw} returned {}", res.status());
+        }
+        let html = res
+            .text()
+            .await
+            .wrap_err_with(|| format!("failed to read {raw}"))?;
+
+        Ok(Some(ArchivedPage { snapshot, html }))
+    }
+
+    /// Retrieve snapshots of many URLs at once, each as close as possible to
+    /// its given timestamp, skipping any the archive doesn't have
+    pub async fn retrieve_all<I>(
+        &self,
+        urls: I,
+        concurrency: usize,
+    ) -> Vec<(Url, ArchivedPage)>
+    where
+        I: IntoIterator<Item = (Url, Option<Timestamp>)>,
+    {
+        stream::iter(urls)
+            .map(|(url, near)| async move {
+                match self.retrieve(&url, near).await {
+                    Ok(Some(page)) => Some((url, page)),
+                    Ok(None) => {
+                        debug!("no archived copy of {url}");
+                        None
+                    }
+                    Err(e) => {
+                        warn!("failed to retrieve archived copy of {url}: {e}");
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(concurrency)
+            .filter_map(|page| async move { page })
+            .collect()
+            .await
+    }
+}
+
+/// Whether a failed attempt at fetching `url` suggests the page is gone for
+/// good, making it worth looking for in the archive
+///
+/// Only web pages that no longer exist (404 or 410) or whose domain no longer
+/// resolves count; other errors, timeouts included, are as likely to be our
+/// fault or fleeting, and local files were never archived.
+pub fn is_dead(url: &Url, page: &eyre::Result<FetchedPage>) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && page.is_err()
+        && matches!(
+            classify(url, page),
+            LinkStatus::ClientError { code: 404 | 410 }
+                | LinkStatus::DnsFailure
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    use crate::{dates::parse_timestamp, http::ProxyConfig};
+
+    /// Serve a minimal imitation of the Wayback Machine on a local port
+    fn stand_in() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let snapshots = base.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                let _ = reader.read_line(&mut request);
+                /* drain the headers so closing doesn't reset the connection */
+                let mut header = String::new();
+                while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                    header.clear();
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+
+                let (status, body) = if path.starts_with("/wayback/available") {
+                    if path.contains("dead.example")
+                        && path.contains("timestamp=20200102")
+                    {
+                        (
+                            "200 OK",
+                            format!(
+                                r#"{{"url": "dead.example/post", "archived_snapshots": {{"closest": {{"status": "200", "available": true, "url": "{snapshots}/web/20200102030405/https://dead.example/post", "timestamp": "20200102030405"}}}}}}"#
+                            ),
+                        )
+                    } else {
+                        ("200 OK", r#"{"archived_snapshots": {}}"#.to_string())
+                    }
+                } else if path
+                    == "/web/20200102030405id_/https://dead.example/post"
+                {
+                    ("200 OK", "<p>Recovered</p>".to_string())
+                } else {
+                    ("404 Not Found", String::new())
+                };
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        Url::parse(&format!("{base}/wayback/available")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retrieve() {
+        let client = crate::http::client(&ProxyConfig::default()).unwrap();
+        let archive = Archive::new(client, stand_in());
+        let bookmarked = parse_timestamp("2020-01-02T13:00:00+10:00");
+
+        let dead = Url::parse("https://dead.example/post").unwrap();
+        let page = archive.retrieve(&dead, bookmarked).await.unwrap().unwrap();
+        assert_eq!(page.html, "<p>Recovered</p>");
+        assert_eq!(
+            page.snapshot.captured,
+            parse_timestamp("2020-01-02T03:04:05Z")
+        );
+        assert!(
+            page.snapshot
+                .url
+                .as_str()
+                .ends_with("/web/20200102030405/https://dead.example/post")
+        );
+
+        let missing = Url::parse("https://never.example/").unwrap();
+        assert!(archive.retrieve(&missing, None).await.unwrap().is_none());
+
+        let recovered = archive
+            .retrieve_all([(dead.clone(), bookmarked), (missing, None)], 2)
+            .await;
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].0, dead);
+    }
+
+    #[test]
+    fn test_is_dead() {
+        use crate::fetch::FetchError;
+        use std::time::Duration;
+
+        let url = Url::parse("https://dead.example/post").unwrap();
+        let status = |status| {
+            Err(FetchError::Status {
+                status,
+                retry_after: None,
+            }
+            .into())
+        };
+
+        assert!(is_dead(&url, &status(404)));
+        assert!(is_dead(&url, &status(410)));
+        assert!(is_dead(
+            &url,
+            &Err(eyre::eyre!("net::ERR_NAME_NOT_RESOLVED at dead.example"))
+        ));
+
+        assert!(!is_dead(
+            &url,
+            &Err(FetchError::Timeout(Duration::from_secs(1)).into())
+        ));
+        assert!(!is_dead(&url, &status(403)));
+        assert!(!is_dead(&url, &status(503)));
+        assert!(!is_dead(&url, &Err(FetchError::RobotsDisallowed.into())));
+        assert!(!is_dead(&url, &Err(eyre::eyre!("page.content failed"))));
+        assert!(!is_dead(
+            &url,
+            &Ok(FetchedPage {
+                url: url.clone(),
+                redirects: vec![],
+                html: "<title>Page Not Found</title>".to_string(),
+            })
+        ));
+
+        let file = Url::parse("file:///home/me/notes.txt").unwrap();
+        assert!(!is_dead(
+            &file,
+            &Err(FetchError::Status {
+                status: 404,
+                retry_after: None
+            }
+            .into())
+        ));
+    }
+}
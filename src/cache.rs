@@ -0,0 +1,162 @@
+use std::{
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use url::Url;
+
+use crate::dates::Timestamp;
+
+/// Page as it was when we last fetched it
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CachedPage {
+    /// URL we requested, which the page is cached under
+    pub url: Url,
+    /// Where we ended up after following any redirects
+    pub final_url: Url,
+    pub fetched: Timestamp,
+    pub html: String,
+}
+
+/// Snapshots of fetched pages that persist between runs, one JSON file per
+/// URL
+#[derive(Clone, Debug)]
+pub struct PageCache {
+    dir: PathBuf,
+}
+
+/// Hex-encoded SHA-256 digest of `bytes`
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+impl PageCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Where the cache lives by default, i.e.
+    /// `$XDG_CACHE_HOME/slurpsearch/pages` (or under `~/.cache`)
+    pub fn default_dir() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })?;
+
+        Some(dir.join(env!("CARGO_PKG_NAME")).join("pages"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path_for(&self, url: &Url) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", sha256_hex(url.as_str().as_bytes())))
+    }
+
+    /// Latest snapshot of `url`, if we have one
+    pub fn get(&self, url: &Url) -> eyre::Result<Option<CachedPage>> {
+        let path = self.path_for(url);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("failed to read {}", path.display())
+                });
+            }
+        };
+
+        serde_json::from_str(&contents)
+            .map(Some)
+            .wrap_err_with(|| format!("corrupt cache entry {}", path.display()))
+    }
+
+    /// Store `page`, replacing any earlier snapshot of the same URL
+    pub fn put(&self, page: &CachedPage) -> eyre::Result<()> {
+        fs::create_dir_all(&self.dir).wrap_err_with(|| {
+            format!("failed to create {}", self.dir.display())
+        })?;
+
+        /* write then rename, so readers never see half an entry */
+        let path = self.path_for(&page.url);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(page)?)
+            .wrap_err_with(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Every cached page, skipping (with a warning) any that can't be read
+    pub fn entries(&self) -> eyre::Result<Vec<CachedPage>> {
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("failed to read {}", self.dir.display())
+                });
+            }
+        };
+
+        let mut pages: Vec<CachedPage> = dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let page = fs::read_to_string(&path)
+                    .map_err(eyre::Report::from)
+                    .and_then(|s| Ok(serde_json::from_str(&s)?));
+                page.inspect_err(|e| {
+                    warn!("skipping cache entry {}: {e}", path.display())
+                })
+                .ok()
+            })
+            .collect();
+        pages.sort_by(|a, b| a.url.cmp(&b.url));
+
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::parse_timestamp;
+
+    #[test]
+    fn test_put_get() {
+        let dir = env::temp_dir()
+            .join(format!("slurpsearch-cache-{}", std::process::id()));
+        let cache = PageCache::new(&dir);
+        let url = Url::parse("https://x.com/a").unwrap();
+        assert_eq!(cache.get(&url).unwrap(), None);
+        assert!(cache.entries().unwrap().is_empty());
+
+        let mut page = CachedPage {
+            url: url.clone(),
+            final_url: Url::parse("https://x.com/a/").unwrap(),
+            fetched: parse_timestamp("2025-12-12T17:18:12+10:00").unwrap(),
+            html: "<p>v1</p>".to_string(),
+        };
+        cache.put(&page).unwrap();
+        page.html = "<p>v2</p>".to_string();
+        cache.put(&page).unwrap();
+
+        assert_eq!(cache.get(&url).unwrap(), Some(page.clone()));
+        assert_eq!(cache.entries().unwrap(), vec![page]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
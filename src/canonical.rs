@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use url::Url;
+
+/// Query parameters that only serve to track where a click came from
+///
+/// A trailing `*` matches any suffix.
+pub const DEFAULT_STRIPPED_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid",
+    "yclid", "igshid", "mc_cid", "mc_eid", "_hsenc", "_hsmi", "mkt_tok",
+    "ref_src",
+];
+
+/// What to do with a slash at the end of a URL's path
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TrailingSlash {
+    /// Leave paths as they are
+    ///
+    /// The default, since a trailing slash can change what a server returns
+    /// and removing one usually just costs a redirect back.
+    #[default]
+    Keep,
+    /// Remove trailing slashes (other than the root path's)
+    Strip,
+    /// Add a trailing slash to paths whose last segment isn't a file name
+    Add,
+}
+
+/// Rules for reducing equivalent URLs to a single form
+///
+/// Hosts are always lowercased and default ports always dropped, as `Url`
+/// does both when parsing HTTP(S) URLs.
+#[derive(Clone, Debug)]
+pub struct CanonicalConfig {
+    /// Query parameters to remove (see [`DEFAULT_STRIPPED_PARAMS`])
+    pub strip_params: Vec<String>,
+    /// Remove fragments (`#section`)
+    pub strip_fragment: bool,
+    pub trailing_slash: TrailingSlash,
+}
+
+impl Default for CanonicalConfig {
+    fn default() -> Self {
+        Self {
+            strip_params: DEFAULT_STRIPPED_PARAMS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            strip_fragment: true,
+            trailing_slash: TrailingSlash::default(),
+        }
+    }
+}
+
+impl CanonicalConfig {
+    fn is_stripped(&self, param: &str) -> bool {
+        self.strip_params.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => param.starts_with(prefix),
+            None => param == p,
+        })
+    }
+
+    pub fn canonicalize(&self, mut url: Url) -> Url {
+        if let Some(host) = url.host_str()
+            && host.ends_with('.')
+        {
+            let host = host.trim_end_matches('.').to_string();
+            let _ = url.set_host(Some(&host));
+        }
+
+        /* filter the raw pairs so the ones we keep aren't re-encoded */
+        if let Some(query) = url.query() {
+            let kept: Vec<&str> = query
+                .split('&')
+                .filter(|pair| {
+                    let name = pair.split('=').next().unwrap_or_default();
+                    !name.is_empty() && !self.is_stripped(name)
+                })
+                .collect();
+            let kept = kept.join("&");
+
+            url.set_query((!kept.is_empty()).then_some(kept.as_str()));
+        }
+
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+
+        let path = url.path().to_string();
+        match self.trailing_slash {
+            TrailingSlash::Keep => {}
+            TrailingSlash::Strip => {
+                if path.len() > 1 && path.ends_with('/') {
+                    url.set_path(path.trim_end_matches('/'));
+                }
+            }
+            TrailingSlash::Add => {
+                let last = path.rsplit('/').next().unwrap_or_default();
+                if !path.ends_with('/') && !last.contains('.') {
+                    url.set_path(&format!("{path}/"));
+                }
+            }
+        }
+
+        url
+    }
+
+    /// Canonicalize `urls`, returning the distinct results along with how
+    /// many URLs turned out to be duplicates of another
+    pub fn dedupe<I>(&self, urls: I) -> (Vec<Url>, usize)
+    where
+        I: IntoIterator<Item = Url>,
+    {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        let mut collapsed = 0;
+
+        for url in urls {
+            let url = self.canonicalize(url);
+            if seen.insert(url.clone()) {
+                out.push(url);
+            } else {
+                collapsed += 1;
+            }
+        }
+
+        (out, collapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let config = CanonicalConfig::default();
+        let cases = [
+            ("https://X.com:443/a", "https://x.com/a"),
+            ("https://x.com/a/", "https://x.com/a/"),
+            ("https://x.com/a#section", "https://x.com/a"),
+            ("https://x.com/", "https://x.com/"),
+            ("https://x.com./a", "https://x.com/a"),
+            ("http://x.com:8080/a", "http://x.com:8080/a"),
+            (
+                "https://x.com/a?utm_source=foo&utm_medium=bar&fbclid=1",
+                "https://x.com/a",
+            ),
+            ("https://x.com/a?id=3&gclid=abc", "https://x.com/a?id=3"),
+        ];
+
+        for (url, expected) in cases {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(config.canonicalize(url).as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = CanonicalConfig {
+            strip_params: vec!["ref".to_string()],
+            strip_fragment: false,
+            trailing_slash: TrailingSlash::Add,
+        };
+        let canonical =
+            |s: &str| config.canonicalize(Url::parse(s).unwrap()).to_string();
+
+        assert_eq!(
+            canonical("https://x.com/a?ref=hn&utm_source=x#s"),
+            "https://x.com/a/?utm_source=x#s"
+        );
+        assert_eq!(
+            canonical("https://x.com/paper.pdf"),
+            "https://x.com/paper.pdf"
+        );
+
+        let config = CanonicalConfig {
+            trailing_slash: TrailingSlash::Strip,
+            ..Default::default()
+        };
+        let canonical =
+            |s: &str| config.canonicalize(Url::parse(s).unwrap()).to_string();
+        assert_eq!(canonical("https://x.com/a/"), "https://x.com/a");
+        assert_eq!(canonical("https://x.com/"), "https://x.com/");
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let urls = [
+            "https://x.com/a?utm_source=foo",
+            "https://x.com/a/",
+            "https://X.com/a#section",
+            "https://x.com/b",
+        ]
+        .map(|s| Url::parse(s).unwrap());
+
+        let (urls, collapsed) = CanonicalConfig::default().dedupe(urls);
+        assert_eq!(urls.len(), 3);
+        assert_eq!(collapsed, 1);
+        assert!(urls.iter().any(|url| url.path() == "/a/"));
+    }
+}
@@ -0,0 +1,373 @@
+use std::{collections::HashMap, fmt::Display};
+
+use clap::ValueEnum;
+use regex::Regex;
+use scraper::{Html, Selector};
+use serde::Serialize;
+use url::Url;
+
+use crate::fetch::{FetchError, FetchOptions, FetchedPage, fetch_all_html};
+
+/// Phrases that give away an error page served with a 200 status
+const NOT_FOUND_REGEX: &str = r"(?i)\b(?:404|not found|page (?:does not|doesn't) exist|no longer (?:exists|available)|(?:could not|couldn't|can't|cannot) (?:be )?found|page (?:is )?missing)\b";
+
+/// A title or heading that announces an error page from the start, rather
+/// than merely mentioning one (as in "HTTP 404 explained")
+const NOT_FOUND_TITLE_REGEX: &str =
+    r"(?i)^\W*(?:(?:error\s*)?404|(?:page\s+)?not\s+found)\b";
+
+/// Pages with less visible text than this are short enough that a "not
+/// found" anywhere in them probably means it
+const SOFT_404_MAX_TEXT: usize = 1000;
+
+/// Errors Chromium and reqwest give for a host that doesn't resolve
+const DNS_ERRORS: &[&str] = &[
+    "ERR_NAME_NOT_RESOLVED",
+    "ERR_NAME_RESOLUTION_FAILED",
+    "dns error",
+];
+
+/// How to print a link report
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Aligned columns for reading
+    #[default]
+    Table,
+    /// JSON array for scripts
+    Json,
+}
+
+/// What happened when we tried to fetch a link
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum LinkStatus {
+    Ok,
+    /// Landed somewhere other than the URL we asked for
+    Redirected {
+        target: Url,
+    },
+    /// 4xx response
+    ClientError {
+        code: u16,
+    },
+    /// 5xx response
+    ServerError {
+        code: u16,
+    },
+    /// The host doesn't exist (anymore)
+    DnsFailure,
+    Timeout,
+    /// Loaded fine, but the page says it doesn't exist
+    SoftNotFound,
+    /// `robots.txt` forbids fetching the page, so we didn't
+    Disallowed,
+    /// Failed in some other way
+    Error {
+        message: String,
+    },
+}
+
+impl LinkStatus {
+    /// Short label for the status column
+    pub fn label(&self) -> String {
+        match self {
+            Self::Ok => "ok".to_string(),
+            Self::Redirected { .. } => "redirected".to_string(),
+            Self::ClientError { code } | Self::ServerError { code } => {
+                code.to_string()
+            }
+            Self::DnsFailure => "dns-failure".to_string(),
+            Self::Timeout => "timeout".to_string(),
+            Self::SoftNotFound => "soft-404".to_string(),
+            Self::Disallowed => "disallowed".to_string(),
+            Self::Error { .. } => "error".to_string(),
+        }
+    }
+
+    /// Whether the link still leads to the page it was saved for
+    pub fn is_alive(&self) -> bool {
+        matches!(self, Self::Ok | Self::Redirected { .. } | Self::Disallowed)
+    }
+}
+
+/// Status of a single haystack URL
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LinkReport {
+    pub url: Url,
+    #[serde(flatten)]
+    pub status: LinkStatus,
+}
+
+impl Display for LinkReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<11} {}", self.status.label(), self.url)?;
+        match &self.status {
+            LinkStatus::Redirected { target } => write!(f, " -> {target}"),
+            LinkStatus::Error { message } => write!(f, " ({message})"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether a page that loaded successfully is really an error page
+///
+/// Longer pages only count if their title or main heading starts off by
+/// saying so, since plenty of articles are about errors.
+pub fn looks_not_found(html: &str) -> bool {
+    let not_found = Regex::new(NOT_FOUND_REGEX).unwrap();
+    let not_found_title = Regex::new(NOT_FOUND_TITLE_REGEX).unwrap();
+    let document = Html::parse_document(html);
+
+    let headings = Selector::parse("title, h1").unwrap();
+    let headings: Vec<String> = document
+        .select(&headings)
+        .map(|el| el.text().collect())
+        .collect();
+    if headings.iter().any(|h| not_found_title.is_match(h)) {
+        return true;
+    }
+
+    let body = Selector::parse("body").unwrap();
+    let text: String = document
+        .select(&body)
+        .flat_map(|el| el.text())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    text.len() < SOFT_404_MAX_TEXT
+        && (not_found.is_match(&text)
+            || headings.iter().any(|h| not_found.is_match(h)))
+}
+
+/// Work out a link's status from the result of fetching it
+pub fn classify(
+    requested: &Url,
+    page: &eyre::Result<FetchedPage>,
+) -> LinkStatus {
+    let e = match page {
+        Ok(page) => {
+            let mut landed = page.url.clone();
+            landed.set_fragment(None);
+            let mut requested = requested.clone();
+            requested.set_fragment(None);
+
+            return if looks_not_found(&page.html) {
+                LinkStatus::SoftNotFound
+            } else if landed != requested {
+                LinkStatus::Redirected {
+                    target: page.url.clone(),
+                }
+            } else {
+                LinkStatus::Ok
+            };
+        }
+        Err(e) => e,
+    };
+
+    match e.downcast_ref::<FetchError>() {
+        Some(FetchError::Status { status, .. }) if *status >= 500 => {
+            LinkStatus::ServerError { code: *status }
+        }
+        Some(FetchError::Status { status, .. }) => {
+            LinkStatus::ClientError { code: *status }
+        }
+        Some(FetchError::Timeout(_)) => LinkStatus::Timeout,
+        Some(FetchError::RobotsDisallowed) => LinkStatus::Disallowed,
+        None if e.chain().any(|cause| {
+            let cause = cause.to_string();
+            DNS_ERRORS.iter().any(|err| cause.contains(err))
+        }) =>
+        {
+            LinkStatus::DnsFailure
+        }
+        None => LinkStatus::Error {
+            message: e.to_string(),
+        },
+    }
+}
+
+/// Fetch every URL and report on its status, in the order given
+pub async fn check<I>(
+    urls: I,
+    options: &FetchOptions,
+) -> eyre::Result<Vec<LinkReport>>
+where
+    I: IntoIterator<Item = Url>,
+{
+    let urls: Vec<Url> = urls.into_iter().collect();
+    let order: HashMap<&Url, usize> =
+        urls.iter().enumerate().map(|(i, url)| (url, i)).collect();
+
+    let mut reports: Vec<LinkReport> = fetch_all_html(urls.clone(), options)
+        .await?
+        .into_iter()
+        .map(|(url, page)| LinkReport {
+            status: classify(&url, &page),
+            url,
+        })
+        .collect();
+    reports.sort_by_key(|report| order.get(&report.url).copied());
+
+    Ok(reports)
+}
+
+/// Render reports in the given format
+pub fn render(
+    reports: &[LinkReport],
+    format: ReportFormat,
+) -> eyre::Result<String> {
+    Ok(match format {
+        ReportFormat::Table => {
+            reports.iter().map(|report| format!("{report}\n")).collect()
+        }
+        ReportFormat::Json => serde_json::to_string_pretty(reports)? + "\n",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn page(url: &str, html: &str) -> eyre::Result<FetchedPage> {
+        Ok(FetchedPage {
+            url: Url::parse(url).unwrap(),
+            redirects: vec![],
+            html: html.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_looks_not_found() {
+        let long = "<p>Plenty to say. </p>".repeat(100);
+        let page = |title: &str, body: &str| {
+            format!("<title>{title}</title><body>{body}</body>")
+        };
+
+        assert!(looks_not_found(&page("404 Not Found", &long)));
+        assert!(looks_not_found(&page("Page not found | Blog", &long)));
+        assert!(looks_not_found(&page(
+            "Blog",
+            &format!("<h1>Error 404</h1>{long}")
+        )));
+        assert!(looks_not_found(&page("Blog | Page not found", "")));
+
+        assert!(!looks_not_found(&page(
+            "Fixing 'command not found' in bash",
+            &long
+        )));
+        assert!(!looks_not_found(&page("HTTP 404 explained", &long)));
+        assert!(!looks_not_found(&page(
+            "Blog",
+            &format!("<h1>Why your links 404</h1>{long}")
+        )));
+    }
+
+    #[test]
+    fn test_classify() {
+        let url = Url::parse("https://x.com/a").unwrap();
+        let article = "<title>A post</title><p>Something worth keeping.</p>";
+
+        assert_eq!(
+            classify(&url, &page("https://x.com/a", article)),
+            LinkStatus::Ok
+        );
+        assert_eq!(
+            classify(&url, &page("https://x.com/b", article)),
+            LinkStatus::Redirected {
+                target: Url::parse("https://x.com/b").unwrap()
+            }
+        );
+        assert_eq!(
+            classify(
+                &url,
+                &page("https://x.com/a", "<title>Page Not Found</title>")
+            ),
+            LinkStatus::SoftNotFound
+        );
+        assert_eq!(
+            classify(
+                &url,
+                &page(
+                    "https://x.com/a",
+                    "<p>Sorry, this page no longer exists.</p>"
+                )
+            ),
+            LinkStatus::SoftNotFound
+        );
+
+        let status = |status| {
+            Err(FetchError::Status {
+                status,
+                retry_after: None,
+            }
+            .into())
+        };
+        assert_eq!(
+            classify(&url, &status(404)),
+            LinkStatus::ClientError { code: 404 }
+        );
+        assert_eq!(
+            classify(&url, &status(503)),
+            LinkStatus::ServerError { code: 503 }
+        );
+        assert_eq!(
+            classify(
+                &url,
+                &Err(FetchError::Timeout(Duration::from_secs(1)).into())
+            ),
+            LinkStatus::Timeout
+        );
+        assert_eq!(
+            classify(
+                &url,
+                &Err(eyre::eyre!("net::ERR_NAME_NOT_RESOLVED at x.com")
+                    .wrap_err("goto(Load) failed"))
+            ),
+            LinkStatus::DnsFailure
+        );
+        /* a site whose robots.txt we couldn't even look up */
+        assert_eq!(
+            classify(
+                &url,
+                &Err(eyre::eyre!(
+                    "error sending request: client error (Connect): dns error: failed to lookup address information"
+                )
+                .wrap_err("robots.txt couldn't be retrieved"))
+            ),
+            LinkStatus::DnsFailure
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let reports = vec![
+            LinkReport {
+                url: Url::parse("https://x.com/a").unwrap(),
+                status: LinkStatus::Ok,
+            },
+            LinkReport {
+                url: Url::parse("https://x.com/b").unwrap(),
+                status: LinkStatus::ClientError { code: 410 },
+            },
+        ];
+
+        assert_eq!(
+            render(&reports, ReportFormat::Table).unwrap(),
+            "ok          https://x.com/a\n410         https://x.com/b\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(
+            &render(&reports, ReportFormat::Json).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json[1],
+            serde_json::json!({
+                "url": "https://x.com/b",
+                "status": "client-error",
+                "code": 410
+            })
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{rag::WebDoc, search::TextPosition};
+
+/// Default upper bound on the size of a single chunk, in (estimated) tokens
+pub const DEFAULT_CHUNK_TOKENS: usize = 512;
+
+/// Default number of (estimated) tokens shared between adjacent chunks
+pub const DEFAULT_CHUNK_OVERLAP: usize = 64;
+
+/// Controls how extracted blocks are packed into embeddable chunks
+#[derive(Copy, Clone, Debug)]
+pub struct ChunkConfig {
+    /// Maximum size of a chunk, in (estimated) tokens
+    pub max_tokens: usize,
+    /// Number of (estimated) tokens repeated at the start of the next chunk
+    pub overlap_tokens: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: DEFAULT_CHUNK_TOKENS,
+            overlap_tokens: DEFAULT_CHUNK_OVERLAP,
+        }
+    }
+}
+
+/// Estimate the number of tokens the embedding model will see for `s`
+///
+/// We don't ship a tokeniser, so this uses the usual rule of thumb of roughly
+/// four characters per token for English text.
+pub fn estimate_tokens(s: &str) -> usize {
+    s.chars().count().div_ceil(4)
+}
+
+/// A single word of a block along with where it came from
+#[derive(Clone, Debug)]
+struct Word<'a> {
+    text: &'a str,
+    block: usize,
+    position: TextPosition,
+    tokens: usize,
+    /// Continues the previous word, which was too long to fit in a chunk
+    joined: bool,
+}
+
+/// Pack the blocks extracted from a single page into chunks
+///
+/// Adjacent small blocks are merged and oversized blocks are split so that
+/// each chunk stays within [`ChunkConfig::max_tokens`]. Chunks prefer to break
+/// on block boundaries and each one begins with the trailing
+/// [`ChunkConfig::overlap_tokens`] of its predecessor, unless the two hold
+/// different [`crate::rag::ContentKind`]s, which are never mixed. The position of every
+/// chunk is that of its first word, so it still points back into the
+/// original block. Words too long for a chunk of their own (such as minified
+/// code, long URLs or base64) are split wherever they need to be.
+pub fn chunk_docs(docs: Vec<WebDoc>, config: &ChunkConfig) -> Vec<WebDoc> {
+    let max_tokens = config.max_tokens.max(1);
+    /* overlap must leave room for new words or we'd never make progress */
+    let overlap_tokens = config.overlap_tokens.min(max_tokens / 2);
+
+    let mut out = Vec::new();
+    let mut current: Vec<Word<'_>> = Vec::new();
+    let mut current_tokens = 0usize;
+    let mut fresh = 0usize;
+
+    for (i, doc) in docs.iter().enumerate() {
+        let words = words_of(doc, i, max_tokens);
+        let block_tokens: usize = words.iter().map(|w| w.tokens).sum();
+
+        // Never mix prose and tabular content within a single chunk.
+        if current
+            .last()
+            .is_some_and(|w| docs[w.block].kind != doc.kind)
+        {
+            if fresh > 0 {
+                out.push(render(&docs, &current));
+            }
+            current.clear();
+            current_tokens = 0;
+            fresh = 0;
+        }
+
+        // Start a new chunk rather than splitting a block that would fit whole.
+        if fresh > 0
+            && current_tokens + block_tokens > max_tokens
+            && block_tokens <= max_tokens
+        {
+            out.push(render(&docs, &current));
+            current = tail(&current, overlap_tokens);
+            current_tokens = current.iter().map(|w| w.tokens).sum();
+            fresh = 0;
+        }
+
+        for word in words {
+            if fresh > 0 && current_tokens + word.tokens > max_tokens {
+                out.push(render(&docs, &current));
+                current = tail(&current, overlap_tokens);
+                current_tokens = current.iter().map(|w| w.tokens).sum();
+                fresh = 0;
+            }
+            current_tokens += word.tokens;
+            current.push(word);
+            fresh += 1;
+        }
+    }
+
+    if fresh > 0 {
+        out.push(render(&docs, &current));
+    }
+
+    out
+}
+
+fn words_of(doc: &WebDoc, block: usize, max_tokens: usize) -> Vec<Word<'_>> {
+    let mut words = Vec::new();
+    let mut start: Option<(usize, usize)> = None; /* (byte, column) */
+
+    for (column, (byte, ch)) in doc.text.char_indices().enumerate() {
+        if ch.is_whitespace() {
+            if let Some((b, c)) = start.take() {
+                words.push(word(doc, block, &doc.text[b..byte], c));
+            }
+        } else if start.is_none() {
+            start = Some((byte, column));
+        }
+    }
+
+    if let Some((b, c)) = start {
+        words.push(word(doc, block, &doc.text[b..], c));
+    }
+
+    words
+        .into_iter()
+        .flat_map(|w| split_word(doc, w, max_tokens))
+        .collect()
+}
+
+/// Split `w` into pieces that each fit in a chunk of `max_tokens`
+fn split_word<'a>(
+    doc: &WebDoc,
+    w: Word<'a>,
+    max_tokens: usize,
+) -> Vec<Word<'a>> {
+    if w.tokens <= max_tokens {
+        return vec![w];
+    }
+
+    /* leave room for the separator, which each piece is still charged */
+    let max_chars = max_tokens.saturating_sub(1).max(1) * 4;
+    let column = w.position.column - doc.position.column;
+    let mut pieces = Vec::new();
+    let mut start = 0usize;
+    let mut chars = 0usize;
+
+    for (n, (byte, _)) in w.text.char_indices().enumerate() {
+        if n > 0 && n % max_chars == 0 {
+            pieces.push((start, byte, chars));
+            start = byte;
+            chars = n;
+        }
+    }
+    pieces.push((start, w.text.len(), chars));
+
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, (from, to, offset))| Word {
+            joined: i > 0,
+            ..word(doc, w.block, &w.text[from..to], column + offset)
+        })
+        .collect()
+}
+
+fn word<'a>(
+    doc: &WebDoc,
+    block: usize,
+    text: &'a str,
+    column: usize,
+) -> Word<'a> {
+    Word {
+        text,
+        block,
+        position: TextPosition {
+            line: doc.position.line,
+            column: doc.position.column + column,
+        },
+        /* include the separating space */
+        tokens: estimate_tokens(text) + 1,
+        joined: false,
+    }
+}
+
+/// Trailing words of `words` whose combined size is at most `budget` tokens
+fn tail<'a>(words: &[Word<'a>], budget: usize) -> Vec<Word<'a>> {
+    let mut total = 0usize;
+    let mut n = 0usize;
+
+    for w in words.iter().rev() {
+        if total + w.tokens > budget {
+            break;
+        }
+        total += w.tokens;
+        n += 1;
+    }
+
+    words[words.len() - n..].to_vec()
+}
+
+fn render(docs: &[WebDoc], words: &[Word<'_>]) -> WebDoc {
+    let first = &words[0];
+    let mut text = String::new();
+
+    for (i, w) in words.iter().enumerate() {
+        if i > 0 && !w.joined {
+            text.push(if words[i - 1].block == w.block {
+                ' '
+            } else {
+                '\n'
+            });
+        }
+        text.push_str(w.text);
+    }
+
+    WebDoc {
+        text,
+        position: first.position,
+        ..docs[first.block].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rag::ContentKind;
+    use url::Url;
+
+    fn doc(line: usize, text: &str, kind: ContentKind) -> WebDoc {
+        WebDoc {
+            url: Url::parse("https://x.com").unwrap(),
+            text: text.to_string(),
+            position: TextPosition { line, column: 1 },
+            anchor: None,
+            kind,
+            seed: None,
+            bookmarked: None,
+            published: None,
+            archived: None,
+        }
+    }
+
+    fn prose(line: usize, text: &str) -> WebDoc {
+        doc(line, text, ContentKind::Prose)
+    }
+
+    fn chunks(docs: Vec<WebDoc>, max: usize, overlap: usize) -> Vec<WebDoc> {
+        chunk_docs(
+            docs,
+            &ChunkConfig {
+                max_tokens: max,
+                overlap_tokens: overlap,
+            },
+        )
+    }
+
+    fn texts(chunks: &[WebDoc]) -> Vec<&str> {
+        chunks.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        /* characters, not bytes */
+        assert_eq!(estimate_tokens("héllo"), 2);
+    }
+
+    #[test]
+    fn test_merge_small_blocks() {
+        /* every three-letter word costs two tokens with its separator */
+        let out = chunks(vec![prose(1, "aaa bbb"), prose(2, "ccc ddd")], 10, 4);
+        assert_eq!(texts(&out), vec!["aaa bbb\nccc ddd"]);
+        assert_eq!(out[0].position, TextPosition { line: 1, column: 1 });
+    }
+
+    #[test]
+    fn test_break_on_block_boundary_with_overlap() {
+        let out = chunks(
+            vec![prose(1, "aaa bbb ccc"), prose(2, "ddd eee fff")],
+            10,
+            4,
+        );
+        assert_eq!(texts(&out), vec!["aaa bbb ccc", "bbb ccc\nddd eee fff"]);
+        /* the overlap still points back into the first block */
+        assert_eq!(out[1].position, TextPosition { line: 1, column: 5 });
+    }
+
+    #[test]
+    fn test_split_oversized_block() {
+        let words: Vec<String> = (1..=12).map(|i| format!("w{i:02}")).collect();
+        let out = chunks(vec![prose(3, &words.join(" "))], 10, 4);
+        assert_eq!(
+            texts(&out),
+            vec![
+                "w01 w02 w03 w04 w05",
+                "w04 w05 w06 w07 w08",
+                "w07 w08 w09 w10 w11",
+                "w10 w11 w12",
+            ]
+        );
+        assert_eq!(
+            out[1].position,
+            TextPosition {
+                line: 3,
+                column: 13
+            }
+        );
+        assert!(out.iter().all(|c| estimate_tokens(&c.text) <= 10));
+    }
+
+    #[test]
+    fn test_split_long_word() {
+        /* e.g. minified JSON in a <pre>, with no whitespace to break on */
+        let blob = "0123456789".repeat(5);
+        let out = chunks(vec![prose(2, &format!("see {blob}"))], 4, 0);
+        assert_eq!(
+            texts(&out),
+            vec![
+                "see",
+                "012345678901",
+                "234567890123",
+                "456789012345",
+                "678901234567",
+                "89",
+            ]
+        );
+        assert_eq!(
+            out[2].position,
+            TextPosition {
+                line: 2,
+                column: 17
+            }
+        );
+        assert!(out.iter().all(|c| estimate_tokens(&c.text) < 4));
+
+        /* pieces that share a chunk are put back together without spaces */
+        let out = chunks(vec![prose(1, &blob)], 10, 4);
+        assert!(out.iter().all(|c| !c.text.contains(' ')));
+        assert!(out.iter().all(|c| estimate_tokens(&c.text) <= 10));
+        assert!(out[0].text.starts_with("0123456789"));
+    }
+
+    #[test]
+    fn test_no_overlap_across_kinds() {
+        let out = chunks(
+            vec![
+                prose(1, "aaa bbb"),
+                doc(2, "ccc | ddd", ContentKind::Tabular),
+                prose(3, "eee"),
+            ],
+            100,
+            4,
+        );
+        assert_eq!(texts(&out), vec!["aaa bbb", "ccc | ddd", "eee"]);
+        assert_eq!(out[1].kind, ContentKind::Tabular);
+    }
+
+    #[test]
+    fn test_overlap_clamped() {
+        /* an overlap as big as the chunk would never make progress */
+        let out = chunks(vec![prose(1, "aaa bbb ccc ddd")], 4, 100);
+        assert_eq!(texts(&out), vec!["aaa bbb", "bbb ccc", "ccc ddd"]);
+        assert!(chunks(vec![], 4, 1).is_empty());
+    }
+}
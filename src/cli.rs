@@ -1,11 +1,265 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use regex::Regex;
+use url::Url;
+
+use crate::{
+    archive::DEFAULT_ARCHIVE_ENDPOINT,
+    canonical::TrailingSlash,
+    check::ReportFormat,
+    chunk::{DEFAULT_CHUNK_OVERLAP, DEFAULT_CHUNK_TOKENS},
+    crawl::DEFAULT_MAX_PAGES,
+    dates::{Timestamp, parse_since, parse_until},
+    discover::DEFAULT_MAX_DISCOVERED,
+    embed::{
+        DEFAULT_BATCH_SIZE, DEFAULT_EMBED_CONCURRENCY, DEFAULT_EMBED_RETRIES,
+        DEFAULT_TOKENS_PER_MINUTE,
+    },
+    fetch::{DEFAULT_RETRIES, DEFAULT_TIMEOUT},
+    index::DEFAULT_MAX_AGE_DAYS,
+    session::HostHeader,
+    settle::{HostSettle, Settle},
+};
 
 #[derive(Clone, Debug, Parser)]
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Opts {
-    /// Path to file to search
-    pub haystack: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Path to file to search, or a WARC file whose pages to search without
+    /// fetching them
+    #[arg(required = true)]
+    pub haystack: Option<PathBuf>,
     /// Search prompt
-    pub prompt: String,
+    #[arg(required = true)]
+    pub prompt: Option<String>,
+    /// Search the persistent index, first bringing it up to date with the
+    /// haystack, rather than fetching and embedding everything afresh
+    #[arg(long, conflicts_with_all = ["depth", "discover", "archive_fallback"])]
+    pub incremental: bool,
+    /// Number of links to follow away from each URL in the haystack
+    #[arg(long, default_value_t = 0)]
+    pub depth: usize,
+    /// Follow links to other origins when crawling
+    #[arg(long)]
+    pub cross_origin: bool,
+    /// Only follow links matching this regular expression (repeatable)
+    #[arg(long = "crawl-include", value_name = "REGEX")]
+    pub crawl_include: Vec<Regex>,
+    /// Never follow links matching this regular expression (repeatable)
+    #[arg(long = "crawl-exclude", value_name = "REGEX")]
+    pub crawl_exclude: Vec<Regex>,
+    /// Maximum number of linked pages to fetch when crawling
+    #[arg(long, default_value_t = DEFAULT_MAX_PAGES)]
+    pub max_pages: usize,
+    /// Expand feeds and site roots into the articles their feeds and
+    /// sitemaps list
+    #[arg(long)]
+    pub discover: bool,
+    /// Maximum number of articles to discover per haystack URL
+    #[arg(long, default_value_t = DEFAULT_MAX_DISCOVERED)]
+    pub max_discovered: usize,
+    /// When a haystack URL is gone (404 or 410) or its domain no longer
+    /// resolves, search the archived snapshot taken closest to when it was
+    /// bookmarked instead (timeouts don't count)
+    #[arg(long)]
+    pub archive_fallback: bool,
+    /// Wayback Machine-compatible availability API to find snapshots with
+    #[arg(long, value_name = "URL", default_value = DEFAULT_ARCHIVE_ENDPOINT)]
+    pub archive_endpoint: Url,
+    /// Don't save fetched pages to the cache that `diff` compares against
+    #[arg(long)]
+    pub no_cache: bool,
+    /// Also save fetched pages to this WARC file (gzipped if it ends in
+    /// `.gz`)
+    #[arg(long, value_name = "PATH")]
+    pub warc_out: Option<PathBuf>,
+    #[command(flatten)]
+    pub indexing: IndexArgs,
+    #[command(flatten)]
+    pub common: CommonOpts,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Fetch every URL in a haystack and report which links are dead
+    Check(CheckOpts),
+    /// Re-fetch every cached page and report which ones have changed
+    Diff(DiffOpts),
+    /// Bring the persistent index up to date with a haystack, fetching and
+    /// embedding only what's new or changed
+    Index(IndexOpts),
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct CheckOpts {
+    /// Path to file to check
+    pub haystack: PathBuf,
+    /// How to print the report
+    #[arg(long, value_enum, default_value_t = ReportFormat::default())]
+    pub format: ReportFormat,
+    #[command(flatten)]
+    pub common: CommonOpts,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct DiffOpts {
+    /// How to print the changes
+    #[arg(long, value_enum, default_value_t = ReportFormat::default())]
+    pub format: ReportFormat,
+    #[command(flatten)]
+    pub common: CommonOpts,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct IndexOpts {
+    /// Path to file to index
+    pub haystack: PathBuf,
+    #[command(flatten)]
+    pub indexing: IndexArgs,
+    #[command(flatten)]
+    pub common: CommonOpts,
+}
+
+/* how documents are embedded and where they're kept (not a doc comment, for
+ * the same reason as `CommonOpts`) */
+#[derive(Clone, Debug, Args)]
+pub struct IndexArgs {
+    /// Maximum size of each embedded chunk, in (estimated) tokens
+    #[arg(long, default_value_t = DEFAULT_CHUNK_TOKENS)]
+    pub chunk_tokens: usize,
+    /// Number of (estimated) tokens shared between adjacent chunks
+    #[arg(long, default_value_t = DEFAULT_CHUNK_OVERLAP)]
+    pub chunk_overlap: usize,
+    /// Keep the persistent index in this file rather than
+    /// `~/.cache/slurpsearch/index.json`
+    #[arg(long, value_name = "PATH")]
+    pub index: Option<PathBuf>,
+    /// Re-fetch indexed pages once they're this many days old
+    #[arg(long, value_name = "DAYS", default_value_t = DEFAULT_MAX_AGE_DAYS)]
+    pub max_age: u64,
+    /// Most documents sent in each embedding request
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    pub batch_size: usize,
+    /// Most embedding requests in flight at once
+    #[arg(long, default_value_t = DEFAULT_EMBED_CONCURRENCY)]
+    pub embed_concurrency: usize,
+    /// Most (estimated) tokens sent for embedding per minute (0 for no
+    /// limit)
+    #[arg(long, value_name = "TOKENS", default_value_t = DEFAULT_TOKENS_PER_MINUTE)]
+    pub tokens_per_minute: u64,
+    /// Number of times to retry rate-limited embedding requests
+    #[arg(long, default_value_t = DEFAULT_EMBED_RETRIES)]
+    pub embed_retries: u32,
+    /// Estimate how many tokens embedding would take and what it would cost,
+    /// without sending anything to the embedding model
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/* options for reading a haystack and fetching its URLs, shared by every
+ * command (not a doc comment, lest clap show it as the program's about) */
+#[derive(Clone, Debug, Args)]
+pub struct CommonOpts {
+    /// Fetch pages even if their site's robots.txt disallows it
+    #[arg(long)]
+    pub ignore_robots: bool,
+    /// Seconds allowed for each attempt at fetching a page
+    #[arg(long, value_name = "SECS", default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    pub timeout: u64,
+    /// Number of times to retry rate-limited (429) or failing (5xx) pages
+    #[arg(long, default_value_t = DEFAULT_RETRIES)]
+    pub retries: u32,
+    /// Let the browser load images, fonts and media (stylesheets are always
+    /// loaded)
+    #[arg(long)]
+    pub load_resources: bool,
+    /// Don't block requests to known ad and tracker domains (which can't be
+    /// blocked through a proxy anyway)
+    #[arg(long)]
+    pub allow_trackers: bool,
+    /// Additionally block requests to this domain and its subdomains
+    /// (repeatable)
+    #[arg(long = "block-domain", value_name = "DOMAIN")]
+    pub block_domains: Vec<String>,
+    /// What to wait for before reading a page: `delay[:MS]`,
+    /// `scroll[:TIMES]`, `idle[:SECS]`, `selector:CSS` or `stable[:SECS]`
+    #[arg(long, value_name = "STRATEGY", default_value_t = Settle::default())]
+    pub settle: Settle,
+    /// Settle strategy for particular hosts, e.g. `*.substack.com=scroll:8`
+    /// (repeatable)
+    #[arg(long = "settle-host", value_name = "HOST=STRATEGY")]
+    pub settle_hosts: Vec<HostSettle>,
+    /// Load cookies from a Netscape cookies.txt or Playwright storage state
+    /// JSON file (repeatable)
+    #[arg(long, value_name = "PATH")]
+    pub cookies: Vec<PathBuf>,
+    /// Send this header, optionally only to matching hosts, e.g.
+    /// `*.corp.example=Authorization: Bearer abc` (repeatable; the browser
+    /// also sends it with everything pages on matching hosts load, unless
+    /// `--direct-for-headers` is given)
+    #[arg(long = "header", value_name = "[HOST=]NAME: VALUE")]
+    pub headers: Vec<HostHeader>,
+    /// Fetch pages that get a `--header` restricted to their host with plain
+    /// HTTP requests rather than the browser, so the header goes nowhere
+    /// else (such pages aren't rendered, so may lack content added by
+    /// JavaScript)
+    #[arg(long)]
+    pub direct_for_headers: bool,
+    /// Identify as this user agent, optionally only to matching hosts
+    /// (repeatable)
+    #[arg(long = "user-agent", value_name = "[HOST=]USER_AGENT")]
+    pub user_agents: Vec<String>,
+    /// Send all traffic through this HTTP(S) or SOCKS5 proxy (defaults to
+    /// `HTTPS_PROXY`, `ALL_PROXY` or `HTTP_PROXY`)
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+    /// Connect to this host directly rather than through the proxy
+    /// (repeatable; defaults to `NO_PROXY`)
+    #[arg(long = "no-proxy", value_name = "HOST")]
+    pub no_proxy: Vec<String>,
+    /// Also strip this query parameter from URLs (repeatable; a trailing `*`
+    /// matches any suffix)
+    #[arg(long = "strip-param", value_name = "NAME")]
+    pub strip_params: Vec<String>,
+    /// Keep this query parameter even though it's stripped by default
+    /// (repeatable)
+    #[arg(long = "keep-param", value_name = "NAME")]
+    pub keep_params: Vec<String>,
+    /// Treat URLs differing only by fragment (`#section`) as distinct
+    #[arg(long)]
+    pub keep_fragments: bool,
+    /// What to do with trailing slashes when canonicalizing URLs
+    #[arg(long, value_enum, default_value_t = TrailingSlash::default())]
+    pub trailing_slash: TrailingSlash,
+    /// Read persistent settings from this file rather than
+    /// `~/.config/slurpsearch/config.toml`
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Keep fetched pages in this directory rather than
+    /// `~/.cache/slurpsearch/pages`
+    #[arg(long, value_name = "PATH")]
+    pub cache_dir: Option<PathBuf>,
+    /// Only consider URLs on this domain or its subdomains (repeatable)
+    #[arg(long = "include-domain", value_name = "DOMAIN")]
+    pub include_domains: Vec<String>,
+    /// Skip URLs on this domain or its subdomains, in addition to those
+    /// excluded in the config file (repeatable)
+    #[arg(long = "exclude-domain", value_name = "DOMAIN")]
+    pub exclude_domains: Vec<String>,
+    /// Only consider URLs matching this regular expression (repeatable)
+    #[arg(long = "url-regex", value_name = "REGEX")]
+    pub url_regexes: Vec<Regex>,
+    /// Only consider links bookmarked (or, if undated, published) on or after
+    /// this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_name = "DATE", value_parser = parse_since)]
+    pub since: Option<Timestamp>,
+    /// Only consider links bookmarked (or, if undated, published) on or before
+    /// this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_name = "DATE", value_parser = parse_until)]
+    pub until: Option<Timestamp>,
 }
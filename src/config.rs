@@ -0,0 +1,80 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::WrapErr;
+use serde::Deserialize;
+use tracing::debug;
+
+/// Settings that persist between runs, read from a TOML file
+///
+/// ```toml
+/// exclude-domains = ["youtube.com", "twitter.com"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Domains whose URLs are always skipped unless explicitly included
+    pub exclude_domains: Vec<String>,
+}
+
+impl Config {
+    /// Where the config file lives by default, i.e.
+    /// `$XDG_CONFIG_HOME/slurpsearch/config.toml` (or under `~/.config`)
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".config"))
+            })?;
+
+        Some(dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+
+    /// Read the config file at `path`, if given, or else at the default
+    /// location (where it's fine for it not to exist)
+    pub fn load(path: Option<&Path>) -> eyre::Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("no config file at {}", path.display());
+                return Ok(Self::default());
+            }
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("failed to read {}", path.display())
+                });
+            }
+        };
+
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("invalid config in {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config: Config =
+            toml::from_str("exclude-domains = [\"youtube.com\", \"x.com\"]\n")
+                .unwrap();
+        assert_eq!(config.exclude_domains, vec!["youtube.com", "x.com"]);
+
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+        assert!(toml::from_str::<Config>("exclude = []").is_err());
+    }
+}
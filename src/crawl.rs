@@ -0,0 +1,458 @@
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::collections::{HashMap, HashSet};
+use tokio::task::LocalSet;
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    canonical::CanonicalConfig,
+    fetch::{BatchFetcher, FetchOptions, FetchedPage},
+};
+
+/// Default maximum number of linked pages to fetch across the whole crawl
+pub const DEFAULT_MAX_PAGES: usize = 500;
+
+/// Controls how far [`crawl`] wanders from the haystack URLs
+#[derive(Clone, Debug)]
+pub struct CrawlConfig {
+    /// Number of links to follow away from each seed (zero disables crawling)
+    pub depth: usize,
+    /// Only follow links that share an origin with the seed they came from
+    pub same_origin: bool,
+    /// If nonempty, only follow links matching at least one of these
+    pub include: Vec<Regex>,
+    /// Never follow links matching any of these
+    pub exclude: Vec<Regex>,
+    /// Maximum number of linked pages to fetch, in addition to the seeds
+    pub max_pages: usize,
+    /// Applied to links before deciding whether we've seen them already
+    pub canonical: CanonicalConfig,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            depth: 0,
+            same_origin: true,
+            include: vec![],
+            exclude: vec![],
+            max_pages: DEFAULT_MAX_PAGES,
+            canonical: CanonicalConfig::default(),
+        }
+    }
+}
+
+impl CrawlConfig {
+    fn allows(&self, seed: &Url, link: &Url) -> bool {
+        if self.same_origin && link.origin() != seed.origin() {
+            return false;
+        }
+
+        if !self.include.is_empty()
+            && !self.include.iter().any(|re| re.is_match(link.as_str()))
+        {
+            return false;
+        }
+
+        !self.exclude.iter().any(|re| re.is_match(link.as_str()))
+    }
+}
+
+/// A page retrieved during a crawl
+#[derive(Debug)]
+pub struct CrawledPage {
+    /// URL we requested, which may have redirected elsewhere
+    pub url: Url,
+    /// Haystack URL this page was reached from (which may be itself)
+    pub seed: Url,
+    /// Number of links followed from the seed to reach this page
+    pub depth: usize,
+    pub page: eyre::Result<FetchedPage>,
+}
+
+/// Fetch the seed URLs and then, level by level, the pages they link to
+///
+/// Every level is retrieved by the same [`BatchFetcher`], so the browser is
+/// only launched once. See [`crawl_with`] for how links are followed.
+pub async fn crawl<I>(
+    seeds: I,
+    fetching: &FetchOptions,
+    config: &CrawlConfig,
+) -> eyre::Result<Vec<CrawledPage>>
+where
+    I: IntoIterator<Item = Url>,
+{
+    LocalSet::new()
+        .run_until(async {
+            let fetcher = BatchFetcher::new(fetching).await?;
+            let pages =
+                crawl_with(seeds, config, |urls| fetcher.fetch_all(urls)).await;
+            fetcher.log_stats();
+            Ok(pages)
+        })
+        .await
+}
+
+/// Crawl from the seed URLs, retrieving each level with a single call to
+/// `fetch`
+///
+/// Pages are never fetched twice, even if reachable from multiple seeds, in
+/// which case they're attributed to whichever seed found them first.
+/// Likewise, pages that redirect to (or declare themselves canonically the
+/// same as) a page we already have are dropped. Each level is processed in
+/// the order its links were found, however `fetch` orders its results, so
+/// that which seed, duplicate and links win out doesn't vary between runs.
+pub async fn crawl_with<I, F, Fut>(
+    seeds: I,
+    config: &CrawlConfig,
+    mut fetch: F,
+) -> Vec<CrawledPage>
+where
+    I: IntoIterator<Item = Url>,
+    F: FnMut(Vec<Url>) -> Fut,
+    Fut: Future<Output = Vec<(Url, eyre::Result<FetchedPage>)>>,
+{
+    let mut visited: HashSet<Url> = HashSet::new();
+    /* (URL to fetch, seed it was reached from), in the order found */
+    let mut frontier: Vec<(Url, Url)> = seeds
+        .into_iter()
+        .filter(|url| visited.insert(url.clone()))
+        .map(|url| (url.clone(), url))
+        .collect();
+    let mut budget = config.max_pages;
+    let mut identities = HashSet::new();
+    let mut duplicates = 0usize;
+    let mut out = Vec::new();
+
+    for depth in 0..=config.depth {
+        if frontier.is_empty() {
+            break;
+        }
+
+        if depth > 0 {
+            info!("Crawling {} linked pages at depth {depth}", frontier.len());
+        }
+
+        let mut fetched: HashMap<Url, eyre::Result<FetchedPage>> =
+            fetch(frontier.iter().map(|(url, _)| url.clone()).collect())
+                .await
+                .into_iter()
+                .collect();
+        let mut next = Vec::new();
+
+        for (url, seed) in frontier {
+            let Some(page) = fetched.remove(&url) else {
+                continue;
+            };
+
+            if let Ok(page) = &page {
+                visited.extend(page.redirects.iter().cloned());
+                visited.insert(page.url.clone());
+
+                if !identities.insert(page.canonical_url()) {
+                    debug!("{url} duplicates a page already fetched");
+                    duplicates += 1;
+                    continue;
+                }
+            }
+
+            if depth < config.depth
+                && let Ok(page) = &page
+            {
+                for link in links(&page.url, &page.html)
+                    .into_iter()
+                    .map(|link| config.canonical.canonicalize(link))
+                {
+                    if budget == 0 {
+                        break;
+                    }
+                    if !config.allows(&seed, &link)
+                        || !visited.insert(link.clone())
+                    {
+                        continue;
+                    }
+                    budget -= 1;
+                    next.push((link, seed.clone()));
+                }
+            }
+
+            out.push(CrawledPage {
+                url,
+                seed,
+                depth,
+                page,
+            });
+        }
+
+        if budget == 0 && depth < config.depth {
+            debug!("crawl budget of {} pages exhausted", config.max_pages);
+        }
+
+        frontier = next;
+    }
+
+    if duplicates > 0 {
+        info!(
+            "Dropped {duplicates} pages that duplicated another by redirect or canonical URL"
+        );
+    }
+
+    out
+}
+
+/// Absolute HTTP(S) URLs of every hyperlink on a page, minus fragments
+fn links(base: &Url, html: &str) -> Vec<Url> {
+    let document = Html::parse_document(html);
+    let sel = Selector::parse("a[href]").unwrap();
+
+    document
+        .select(&sel)
+        .filter_map(|a| a.value().attr("href"))
+        .filter_map(|href| base.join(href).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .map(|mut url| {
+            url.set_fragment(None);
+            url
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pages by URL, each with where it redirects to (if anywhere) and its
+    /// links
+    fn site(
+        pages: &[(&str, Option<&str>, &[&str])],
+    ) -> HashMap<Url, FetchedPage> {
+        pages
+            .iter()
+            .map(|(url, redirect, links)| {
+                let url = Url::parse(url).unwrap();
+                let html = links
+                    .iter()
+                    .map(|href| format!(r#"<a href="{href}">link</a>"#))
+                    .collect();
+                let page = match redirect {
+                    Some(target) => FetchedPage {
+                        url: Url::parse(target).unwrap(),
+                        redirects: vec![url.clone()],
+                        html,
+                    },
+                    None => FetchedPage {
+                        url: url.clone(),
+                        redirects: vec![],
+                        html,
+                    },
+                };
+                (url, page)
+            })
+            .collect()
+    }
+
+    /// Crawl `site` from `seeds`, returning the URLs requested at each level
+    /// and the pages kept
+    ///
+    /// Results come back in the reverse of the order requested, much as they
+    /// would if the last pages happened to load quickest.
+    async fn run_from(
+        site: &HashMap<Url, FetchedPage>,
+        seeds: &[&str],
+        config: &CrawlConfig,
+    ) -> (Vec<Vec<String>>, Vec<CrawledPage>) {
+        let mut levels = vec![];
+        let seeds = seeds.iter().map(|seed| Url::parse(seed).unwrap());
+        let pages = crawl_with(seeds, config, |urls| {
+            let mut requested: Vec<String> =
+                urls.iter().map(|url| url.to_string()).collect();
+            requested.sort();
+            levels.push(requested);
+
+            let fetched = urls
+                .into_iter()
+                .rev()
+                .map(|url| {
+                    let page = site
+                        .get(&url)
+                        .cloned()
+                        .ok_or_else(|| eyre::eyre!("{url} not found"));
+                    (url, page)
+                })
+                .collect();
+            async move { fetched }
+        })
+        .await;
+        (levels, pages)
+    }
+
+    /// Crawl `site` from a single seed, as with [`run_from`]
+    async fn run(
+        site: &HashMap<Url, FetchedPage>,
+        seed: &str,
+        config: &CrawlConfig,
+    ) -> (Vec<Vec<String>>, Vec<CrawledPage>) {
+        run_from(site, &[seed], config).await
+    }
+
+    fn blog() -> HashMap<Url, FetchedPage> {
+        site(&[
+            (
+                "https://a.example/",
+                None,
+                &[
+                    "/one",
+                    "two",
+                    "/one#comments",
+                    "https://b.example/elsewhere",
+                    "/skip/this",
+                    "mailto:me@a.example",
+                ],
+            ),
+            ("https://a.example/one", None, &["/", "/three", "two"]),
+            ("https://a.example/two", None, &["/three"]),
+            ("https://a.example/three", None, &["/four"]),
+            ("https://b.example/elsewhere", None, &[]),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_crawl_levels() {
+        let config = CrawlConfig {
+            depth: 2,
+            exclude: vec![Regex::new("/skip/").unwrap()],
+            ..Default::default()
+        };
+        let (levels, pages) = run(&blog(), "https://a.example/", &config).await;
+
+        /* every page is requested once, however many pages link to it */
+        assert_eq!(
+            levels,
+            vec![
+                vec!["https://a.example/"],
+                vec!["https://a.example/one", "https://a.example/two"],
+                vec!["https://a.example/three"],
+            ]
+        );
+        assert!(
+            pages
+                .iter()
+                .all(|p| p.seed.as_str() == "https://a.example/")
+        );
+        let depths: HashMap<String, usize> =
+            pages.iter().map(|p| (p.url.to_string(), p.depth)).collect();
+        assert_eq!(depths["https://a.example/three"], 2);
+        assert_eq!(depths.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_filters() {
+        let cross_origin = CrawlConfig {
+            depth: 1,
+            same_origin: false,
+            ..Default::default()
+        };
+        let (levels, _) =
+            run(&blog(), "https://a.example/", &cross_origin).await;
+        assert_eq!(
+            levels[1],
+            vec![
+                "https://a.example/one",
+                "https://a.example/skip/this",
+                "https://a.example/two",
+                "https://b.example/elsewhere",
+            ]
+        );
+
+        let include = CrawlConfig {
+            depth: 1,
+            include: vec![Regex::new("/tw").unwrap()],
+            ..Default::default()
+        };
+        let (levels, _) = run(&blog(), "https://a.example/", &include).await;
+        assert_eq!(levels[1], vec!["https://a.example/two"]);
+
+        /* the budget counts linked pages across all levels */
+        let budget = CrawlConfig {
+            depth: 5,
+            max_pages: 2,
+            ..Default::default()
+        };
+        let (levels, pages) = run(&blog(), "https://a.example/", &budget).await;
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[1].len(), 2);
+        assert_eq!(pages.len(), 3);
+
+        let none = CrawlConfig::default();
+        let (levels, pages) = run(&blog(), "https://a.example/", &none).await;
+        assert_eq!(levels, vec![vec!["https://a.example/"]]);
+        assert_eq!(pages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_drops_redirect_duplicates() {
+        let site = site(&[
+            ("https://a.example/", None, &["/old", "/new"]),
+            ("https://a.example/old", Some("https://a.example/new"), &[]),
+            ("https://a.example/new", None, &[]),
+        ]);
+        let config = CrawlConfig {
+            depth: 1,
+            ..Default::default()
+        };
+        let (_, pages) = run(&site, "https://a.example/", &config).await;
+
+        let finals: Vec<&str> = pages
+            .iter()
+            .map(|p| p.page.as_ref().unwrap().url.as_str())
+            .collect();
+        assert_eq!(finals.len(), 2);
+        assert_eq!(
+            finals
+                .iter()
+                .filter(|url| **url == "https://a.example/new")
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crawl_order() {
+        let site = site(&[
+            ("https://a.example/", None, &["/shared", "/old", "/new"]),
+            ("https://a.example/blog/", None, &["/shared", "/later"]),
+            ("https://a.example/shared", None, &[]),
+            ("https://a.example/old", Some("https://a.example/new"), &[]),
+            ("https://a.example/new", None, &[]),
+            ("https://a.example/later", None, &[]),
+        ]);
+        let config = CrawlConfig {
+            depth: 1,
+            max_pages: 3,
+            ..Default::default()
+        };
+        let (_, pages) = run_from(
+            &site,
+            &["https://a.example/", "https://a.example/blog/"],
+            &config,
+        )
+        .await;
+
+        /* pages come out in the order found, attributed to the first seed to
+        find them, with the budget spent on the first links found */
+        let found: Vec<(&str, &str)> = pages
+            .iter()
+            .map(|p| (p.url.as_str(), p.seed.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("https://a.example/", "https://a.example/"),
+                ("https://a.example/blog/", "https://a.example/blog/"),
+                ("https://a.example/shared", "https://a.example/"),
+                ("https://a.example/old", "https://a.example/"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use chrono::{
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone, Utc,
+};
+use regex::Regex;
+use scraper::{Html, Selector};
+
+/// A point in time along with the offset it was written in
+pub type Timestamp = DateTime<FixedOffset>;
+
+/// `<meta>` tags that commonly carry a page's publication date, in order of
+/// preference
+const PUBLISHED_META: &[&str] = &[
+    r#"meta[property="article:published_time"]"#,
+    r#"meta[name="article:published_time"]"#,
+    r#"meta[itemprop="datePublished"]"#,
+    r#"meta[name="citation_publication_date"]"#,
+    r#"meta[name="dc.date"]"#,
+    r#"meta[name="DC.date.issued"]"#,
+    r#"meta[name="date"]"#,
+];
+
+/// Parse a timestamp as found in haystacks and page metadata
+///
+/// Accepts RFC 3339 (e.g., `2025-12-12T17:18:12+10:00`), as well as dates
+/// and date-times without an offset, which are taken to be UTC.
+pub fn parse_timestamp(s: &str) -> Option<Timestamp> {
+    let s = s.trim();
+
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Some(t);
+    }
+
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|d| d.and_time(NaiveTime::MIN))
+        })
+        .ok()?;
+
+    Some(Utc.from_utc_datetime(&naive).fixed_offset())
+}
+
+/// Parse a bound given on the command line, where a plain date means the
+/// start (or, if `end_of_day`, the end) of that day in the local timezone
+fn parse_bound(s: &str, end_of_day: bool) -> Result<Timestamp, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s.trim()) {
+        return Ok(t);
+    }
+
+    let date =
+        NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").map_err(|_| {
+            format!("expected YYYY-MM-DD or an RFC 3339 timestamp, got `{s}`")
+        })?;
+    let time = if end_of_day {
+        NaiveTime::from_hms_milli_opt(23, 59, 59, 999).unwrap()
+    } else {
+        NaiveTime::MIN
+    };
+
+    Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|t| t.fixed_offset())
+        .ok_or_else(|| format!("`{s}` doesn't exist in the local timezone"))
+}
+
+/// Parser for `--since`
+pub fn parse_since(s: &str) -> Result<Timestamp, String> {
+    parse_bound(s, false)
+}
+
+/// Parser for `--until`, which includes the whole of a given day
+pub fn parse_until(s: &str) -> Result<Timestamp, String> {
+    parse_bound(s, true)
+}
+
+/// Inclusive window of time, open at either end
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub since: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+}
+
+impl DateRange {
+    /// Whether the range excludes anything at all
+    pub fn is_bounded(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    pub fn contains(&self, t: &Timestamp) -> bool {
+        self.since.is_none_or(|since| *t >= since)
+            && self.until.is_none_or(|until| *t <= until)
+    }
+}
+
+/// When a page says it was published, according to its metadata
+pub fn published_date(html: &str) -> Option<Timestamp> {
+    let document = Html::parse_document(html);
+
+    let from_meta = PUBLISHED_META.iter().find_map(|sel| {
+        let sel = Selector::parse(sel).unwrap();
+        document
+            .select(&sel)
+            .filter_map(|meta| meta.value().attr("content"))
+            .find_map(parse_timestamp)
+    });
+    if from_meta.is_some() {
+        return from_meta;
+    }
+
+    let ld_json =
+        Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    let date_published =
+        Regex::new(r#""datePublished"\s*:\s*"([^"]+)""#).unwrap();
+    let from_ld_json = document.select(&ld_json).find_map(|script| {
+        let json = script.text().collect::<String>();
+        date_published
+            .captures(&json)
+            .and_then(|c| parse_timestamp(&c[1]))
+    });
+    if from_ld_json.is_some() {
+        return from_ld_json;
+    }
+
+    let time =
+        Selector::parse("time[pubdate][datetime], article time[datetime]")
+            .unwrap();
+    document
+        .select(&time)
+        .filter_map(|t| t.value().attr("datetime"))
+        .find_map(parse_timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("2025-12-12T17:18:12+10:00"),
+            Some(ts("2025-12-12T17:18:12+10:00"))
+        );
+        assert_eq!(
+            parse_timestamp("2025-12-12"),
+            Some(ts("2025-12-12T00:00:00Z"))
+        );
+        assert_eq!(
+            parse_timestamp("2025-12-12 07:18:12"),
+            Some(ts("2025-12-12T07:18:12Z"))
+        );
+        assert_eq!(parse_timestamp("last tuesday"), None);
+    }
+
+    #[test]
+    fn test_date_range() {
+        let range = DateRange {
+            since: Some(ts("2025-11-01T00:00:00Z")),
+            until: parse_until("2025-11-30T23:59:59Z").ok(),
+        };
+        assert!(range.is_bounded());
+        assert!(range.contains(&ts("2025-11-15T12:00:00+10:00")));
+        assert!(range.contains(&ts("2025-12-01T09:00:00+10:00")));
+        assert!(!range.contains(&ts("2025-12-01T11:00:00+10:00")));
+        assert!(!range.contains(&ts("2025-10-31T23:59:59Z")));
+        assert!(DateRange::default().contains(&ts("1970-01-01T00:00:00Z")));
+
+        assert!(parse_since("2025-11-01").is_ok());
+        assert!(parse_until("November").is_err());
+    }
+
+    #[test]
+    fn test_published_date() {
+        let meta = r#"<html><head>
+<meta property="article:published_time" content="2025-03-04T05:06:07Z">
+</head><body><time datetime="2020-01-01">old</time></body></html>"#;
+        assert_eq!(published_date(meta), Some(ts("2025-03-04T05:06:07Z")));
+
+        let ld_json = r#"<html><head><script type="application/ld+json">
+{"@type": "BlogPosting", "datePublished": "2024-06-01"}
+</script></head><body></body></html>"#;
+        assert_eq!(published_date(ld_json), Some(ts("2024-06-01T00:00:00Z")));
+
+        let time = r#"<article><h1>Post</h1>
+<time datetime="2023-02-03T10:00:00+01:00">3 Feb</time></article>"#;
+        assert_eq!(published_date(time), Some(ts("2023-02-03T10:00:00+01:00")));
+
+        assert_eq!(published_date("<p>undated</p>"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{check::ReportFormat, dates::Timestamp, sites::ExtractorRegistry};
+
+/// How a page's text changed since its cached snapshot
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PageDiff {
+    pub url: Url,
+    /// When the snapshot we compared against was taken
+    pub previous: Timestamp,
+    /// Paragraphs only in the current page
+    pub added: Vec<String>,
+    /// Paragraphs only in the snapshot
+    pub removed: Vec<String>,
+}
+
+impl PageDiff {
+    /// Compare the text of two versions of the page at `url`, returning
+    /// `None` if nothing meaningful changed
+    pub fn between(
+        extractors: &ExtractorRegistry,
+        url: &Url,
+        previous: (Timestamp, &str),
+        current: &str,
+    ) -> Option<Self> {
+        let old = paragraphs(extractors, url, previous.1);
+        let new = paragraphs(extractors, url, current);
+        let (added, removed) = diff_paragraphs(&old, &new);
+
+        (!added.is_empty() || !removed.is_empty()).then(|| Self {
+            url: url.clone(),
+            previous: previous.0,
+            added,
+            removed,
+        })
+    }
+}
+
+impl Display for PageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} (changed since {})", self.url, self.previous)?;
+        for paragraph in &self.removed {
+            writeln!(f, "  - {paragraph}")?;
+        }
+        for paragraph in &self.added {
+            writeln!(f, "  + {paragraph}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Text of each block extracted from a page, with whitespace normalized so
+/// that reformatting alone doesn't count as a change
+pub fn paragraphs(
+    extractors: &ExtractorRegistry,
+    url: &Url,
+    html: &str,
+) -> Vec<String> {
+    extractors
+        .extract(url.clone(), html)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|doc| doc.text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+/// Paragraphs added to and removed from `old` to get `new`, in the order they
+/// appear
+///
+/// Paragraphs that merely moved don't count. Repeated paragraphs are matched
+/// up one for one.
+pub fn diff_paragraphs(
+    old: &[String],
+    new: &[String],
+) -> (Vec<String>, Vec<String>) {
+    fn only_in(a: &[String], b: &[String]) -> Vec<String> {
+        let mut remaining: HashMap<&str, usize> = HashMap::new();
+        for p in b {
+            *remaining.entry(p).or_default() += 1;
+        }
+
+        a.iter()
+            .filter(|p| match remaining.get_mut(p.as_str()) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    (only_in(new, old), only_in(old, new))
+}
+
+/// Render diffs in the given format
+pub fn render(
+    diffs: &[PageDiff],
+    format: ReportFormat,
+) -> eyre::Result<String> {
+    Ok(match format {
+        ReportFormat::Table => {
+            diffs.iter().map(|diff| format!("{diff}\n")).collect()
+        }
+        ReportFormat::Json => serde_json::to_string_pretty(diffs)? + "\n",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::parse_timestamp;
+
+    fn strings(ps: &[&str]) -> Vec<String> {
+        ps.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff_paragraphs() {
+        let old = strings(&["intro", "step 1", "step 2", "thanks", "thanks"]);
+        let new = strings(&["intro", "step 2", "step 1", "step 3", "thanks"]);
+
+        let (added, removed) = diff_paragraphs(&old, &new);
+        assert_eq!(added, strings(&["step 3"]));
+        assert_eq!(removed, strings(&["thanks"]));
+
+        assert_eq!(diff_paragraphs(&old, &old), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_between() {
+        let extractors = ExtractorRegistry::default();
+        let url = Url::parse("https://x.com/advisory").unwrap();
+        let previous = parse_timestamp("2025-12-01").unwrap();
+        let v1 = "<main><p>Affects versions before 1.2.</p>\n<p>No fix yet.</p></main>";
+        let v1_reflowed = "<main><p>Affects   versions\nbefore 1.2.</p><p>No fix yet.</p></main>";
+        let v2 = "<main><p>Affects versions before 1.2.</p><p>Fixed in 1.2.1.</p></main>";
+
+        assert_eq!(
+            PageDiff::between(&extractors, &url, (previous, v1), v1_reflowed),
+            None
+        );
+
+        let diff =
+            PageDiff::between(&extractors, &url, (previous, v1), v2).unwrap();
+        assert_eq!(diff.added, strings(&["Fixed in 1.2.1."]));
+        assert_eq!(diff.removed, strings(&["No fix yet."]));
+        assert_eq!(
+            diff.to_string(),
+            format!(
+                "{url} (changed since {previous})\n  - No fix yet.\n  + Fixed in 1.2.1.\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use futures::{StreamExt, stream};
+use regex::Regex;
+use scraper::{Html, Selector};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Default maximum number of article URLs a single seed may expand into
+pub const DEFAULT_MAX_DISCOVERED: usize = 200;
+
+/// Maximum number of child sitemaps read from a sitemap index
+const MAX_CHILD_SITEMAPS: usize = 16;
+
+/// Result of expanding the haystack URLs into the pages they list
+#[derive(Clone, Debug, Default)]
+pub struct Discovered {
+    /// URLs to fetch, i.e. ordinary seeds followed by discovered articles
+    pub urls: Vec<Url>,
+    /// Haystack URL each discovered article was listed by
+    pub seeds: HashMap<Url, Url>,
+}
+
+impl Discovered {
+    /// Haystack URL that `url` ultimately came from
+    pub fn seed_of<'a>(&'a self, url: &'a Url) -> &'a Url {
+        self.seeds.get(url).unwrap_or(url)
+    }
+}
+
+/// Expand seeds into the articles listed by their feeds and sitemaps
+///
+/// Seeds that are themselves RSS or Atom feeds are replaced by the entries
+/// they contain. Seeds that look like the root of a site (i.e., whose path
+/// ends in `/`) are kept and supplemented by the URLs listed in their
+/// `<link rel="alternate">` feeds, `robots.txt` `Sitemap:` entries and
+/// `/sitemap.xml`. Sitemaps cover the whole site, so only their pages on
+/// the seed's host and under its path are used. At most `max_per_seed` URLs
+/// are added for each seed.
+pub async fn discover<I>(
+    client: &reqwest::Client,
+    seeds: I,
+    concurrency: usize,
+    max_per_seed: usize,
+) -> Discovered
+where
+    I: IntoIterator<Item = Url>,
+{
+    let expansions: Vec<(Url, Expansion)> = stream::iter(seeds)
+        .map(|seed| async move {
+            let expansion = expand(client, &seed, max_per_seed).await;
+            (seed, expansion)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let mut out = Discovered::default();
+    let mut seen = HashSet::new();
+    let mut feeds = 0usize;
+
+    for (seed, expansion) in &expansions {
+        if !expansion.is_feed && seen.insert(seed.clone()) {
+            out.urls.push(seed.clone());
+        }
+        feeds += usize::from(expansion.is_feed);
+    }
+
+    for (seed, expansion) in expansions {
+        for url in expansion.articles {
+            if seen.insert(url.clone()) {
+                out.urls.push(url.clone());
+                out.seeds.insert(url, seed.clone());
+            }
+        }
+    }
+
+    info!(
+        "Discovered {} article URLs ({} seeds were feeds)",
+        out.seeds.len(),
+        feeds
+    );
+
+    out
+}
+
+#[derive(Debug, Default)]
+struct Expansion {
+    is_feed: bool,
+    articles: Vec<Url>,
+}
+
+async fn expand(
+    client: &reqwest::Client,
+    seed: &Url,
+    max_per_seed: usize,
+) -> Expansion {
+    if !matches!(seed.scheme(), "http" | "https") {
+        return Expansion::default();
+    }
+
+    let body = match get(client, seed).await {
+        Some(body) => body,
+        None => return Expansion::default(),
+    };
+
+    if is_feed(&body) {
+        debug!("{seed} is a feed");
+        let mut articles = feed_entries(&body, seed);
+        articles.truncate(max_per_seed);
+        return Expansion {
+            is_feed: true,
+            articles,
+        };
+    }
+
+    if !seed.path().ends_with('/') {
+        return Expansion::default();
+    }
+
+    let mut articles = Vec::new();
+    let mut add = |urls: Vec<Url>| {
+        for url in urls {
+            if articles.len() < max_per_seed && !articles.contains(&url) {
+                articles.push(url);
+            }
+        }
+    };
+
+    for feed in feed_links(&body, seed) {
+        if let Some(body) = get(client, &feed).await {
+            add(feed_entries(&body, &feed));
+        }
+    }
+
+    let mut sitemaps = Vec::new();
+    if let Ok(robots) = seed.join("/robots.txt")
+        && let Some(body) = get(client, &robots).await
+    {
+        sitemaps.extend(robots_sitemaps(&body));
+    }
+    if sitemaps.is_empty()
+        && let Ok(sitemap) = seed.join("/sitemap.xml")
+    {
+        sitemaps.push(sitemap);
+    }
+
+    let mut children = Vec::new();
+    for sitemap in sitemaps {
+        if let Some(body) = get(client, &sitemap).await {
+            let (urls, nested) = sitemap_entries(&body);
+            add(urls.into_iter().filter(|url| is_under(seed, url)).collect());
+            children.extend(nested);
+        }
+    }
+    for sitemap in children.into_iter().take(MAX_CHILD_SITEMAPS) {
+        if let Some(body) = get(client, &sitemap).await {
+            add(sitemap_entries(&body)
+                .0
+                .into_iter()
+                .filter(|url| is_under(seed, url))
+                .collect());
+        }
+    }
+
+    Expansion {
+        is_feed: false,
+        articles,
+    }
+}
+
+/// Whether `url` is on the same host as `seed` and within its path
+fn is_under(seed: &Url, url: &Url) -> bool {
+    url.host_str() == seed.host_str() && url.path().starts_with(seed.path())
+}
+
+/// Body of `url` if it could be retrieved successfully
+async fn get(client: &reqwest::Client, url: &Url) -> Option<String> {
+    let res = match client.get(url.clone()).send().await {
+        Ok(res) => res,
+        Err(e) => {
+            warn!("discovery request for {url} failed: {e}");
+            return None;
+        }
+    };
+
+    if !res.status().is_success() {
+        debug!("discovery request for {url} returned {}", res.status());
+        return None;
+    }
+
+    res.text().await.ok()
+}
+
+fn is_feed(body: &str) -> bool {
+    let head: String = body.chars().take(1024).collect();
+    Regex::new(r"<(rss|feed|rdf:RDF)[\s>]")
+        .unwrap()
+        .is_match(&head)
+}
+
+/// Links to the articles in an RSS or Atom feed
+fn feed_entries(body: &str, base: &Url) -> Vec<Url> {
+    let rss_item = Regex::new(r"(?s)<item\b.*?</item>").unwrap();
+    let rss_link = Regex::new(r"(?s)<link>(.*?)</link>").unwrap();
+    let atom_entry = Regex::new(r"(?s)<entry\b.*?</entry>").unwrap();
+    let atom_link = Regex::new(r"<link\b[^>]*>").unwrap();
+
+    let rss = rss_item.find_iter(body).filter_map(|item| {
+        rss_link.captures(item.as_str()).map(|c| c[1].to_string())
+    });
+
+    let atom = atom_entry.find_iter(body).filter_map(|entry| {
+        atom_link
+            .find_iter(entry.as_str())
+            .map(|tag| tag.as_str())
+            .find(|tag| attr(tag, "rel").is_none_or(|rel| rel == "alternate"))
+            .and_then(|tag| attr(tag, "href"))
+    });
+
+    rss.chain(atom)
+        .filter_map(|link| base.join(&xml_text(&link)).ok())
+        .collect()
+}
+
+/// `<loc>`s of a sitemap, split into pages and nested sitemaps
+fn sitemap_entries(body: &str) -> (Vec<Url>, Vec<Url>) {
+    let loc = Regex::new(r"(?s)<loc>(.*?)</loc>").unwrap();
+    let urls = loc
+        .captures_iter(body)
+        .filter_map(|c| Url::parse(&xml_text(&c[1])).ok())
+        .collect();
+
+    if body.contains("<sitemapindex") {
+        (vec![], urls)
+    } else {
+        (urls, vec![])
+    }
+}
+
+/// URLs of the `Sitemap:` entries in a `robots.txt`
+fn robots_sitemaps(body: &str) -> Vec<Url> {
+    body.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case("sitemap")
+                .then(|| Url::parse(value.trim()).ok())?
+        })
+        .collect()
+}
+
+/// Feeds advertised by an HTML page via `<link rel="alternate">`
+fn feed_links(html: &str, base: &Url) -> Vec<Url> {
+    let document = Html::parse_document(html);
+    let sel = Selector::parse("link[rel~=alternate][href][type]").unwrap();
+
+    document
+        .select(&sel)
+        .filter(|link| {
+            link.value().attr("type").is_some_and(|t| {
+                matches!(t, "application/rss+xml" | "application/atom+xml")
+            })
+        })
+        .filter_map(|link| base.join(link.value().attr("href")?).ok())
+        .collect()
+}
+
+/// Value of attribute `name` within a single XML tag
+fn attr(tag: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"\b{name}\s*=\s*["']([^"']*)["']"#)).ok()?;
+    re.captures(tag).map(|c| c[1].to_string())
+}
+
+/// Unwrap CDATA and decode the handful of entities allowed in XML
+fn xml_text(s: &str) -> String {
+    let s = s.trim();
+    let s = s
+        .strip_prefix("<![CDATA[")
+        .and_then(|s| s.strip_suffix("]]>"))
+        .unwrap_or(s);
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rss_entries() {
+        let body = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel>
+  <title>Blog</title><link>https://blog.example.com/</link>
+  <item><title>One</title><link>https://blog.example.com/one?a=1&amp;b=2</link></item>
+  <item><title>Two</title><link><![CDATA[/two]]></link></item>
+</channel></rss>"#;
+        let base = Url::parse("https://blog.example.com/feed.xml").unwrap();
+        assert!(is_feed(body));
+        assert_eq!(
+            feed_entries(body, &base),
+            vec![
+                Url::parse("https://blog.example.com/one?a=1&b=2").unwrap(),
+                Url::parse("https://blog.example.com/two").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_atom_entries() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <link href="https://blog.example.com/atom.xml" rel="self"/>
+  <entry>
+    <link rel="replies" href="https://blog.example.com/one#comments"/>
+    <link href="https://blog.example.com/one" rel="alternate"/>
+  </entry>
+  <entry><link href="https://blog.example.com/two"/></entry>
+</feed>"#;
+        let base = Url::parse("https://blog.example.com/atom.xml").unwrap();
+        assert!(is_feed(body));
+        assert_eq!(
+            feed_entries(body, &base),
+            vec![
+                Url::parse("https://blog.example.com/one").unwrap(),
+                Url::parse("https://blog.example.com/two").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sitemaps() {
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/posts.xml</loc></sitemap>
+</sitemapindex>"#;
+        assert_eq!(
+            sitemap_entries(index),
+            (
+                vec![],
+                vec![Url::parse("https://example.com/posts.xml").unwrap()]
+            )
+        );
+
+        let urlset = r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc> https://example.com/a </loc><lastmod>2025-01-01</lastmod></url>
+</urlset>"#;
+        assert_eq!(
+            sitemap_entries(urlset),
+            (vec![Url::parse("https://example.com/a").unwrap()], vec![])
+        );
+
+        let robots = "User-agent: *\nDisallow: /admin\nSitemap: https://example.com/sitemap_index.xml\n";
+        assert_eq!(
+            robots_sitemaps(robots),
+            vec![Url::parse("https://example.com/sitemap_index.xml").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_is_under() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        let blog = url("https://example.com/blog/");
+
+        assert!(is_under(&blog, &url("https://example.com/blog/post")));
+        assert!(is_under(&blog, &url("http://example.com/blog/a/b")));
+        assert!(!is_under(&blog, &url("https://example.com/about")));
+        assert!(!is_under(&blog, &url("https://example.com/blogroll")));
+        assert!(!is_under(&blog, &url("https://cdn.example.com/blog/post")));
+        assert!(is_under(
+            &url("https://example.com/"),
+            &url("https://example.com/anything")
+        ));
+    }
+
+    #[test]
+    fn test_feed_links() {
+        let html = r#"<html><head>
+<link rel="alternate" type="application/rss+xml" href="/index.xml">
+<link rel="alternate" type="text/html" hreflang="fr" href="/fr/">
+<link rel="stylesheet" href="/style.css">
+</head><body></body></html>"#;
+        let base = Url::parse("https://blog.example.com/").unwrap();
+        assert!(!is_feed(html));
+        assert_eq!(
+            feed_links(html, &base),
+            vec![Url::parse("https://blog.example.com/index.xml").unwrap()]
+        );
+    }
+}
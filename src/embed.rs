@@ -0,0 +1,455 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use eyre::WrapErr;
+use futures::{StreamExt, TryStreamExt, stream};
+use regex::Regex;
+use rig::{
+    OneOrMany,
+    embeddings::{Embedding, EmbeddingError, EmbeddingModel},
+};
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant, sleep},
+};
+use tracing::{info, warn};
+
+use crate::{chunk::estimate_tokens, rag::WebDoc};
+
+/// Default number of documents sent in each embedding request
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Default number of embedding requests in flight at once
+pub const DEFAULT_EMBED_CONCURRENCY: usize = 4;
+
+/// Default cap on (estimated) tokens sent per minute, matching OpenAI's
+/// lowest usage tier for embeddings
+pub const DEFAULT_TOKENS_PER_MINUTE: u64 = 1_000_000;
+
+/// Default number of times a rate-limited embedding request is retried
+pub const DEFAULT_EMBED_RETRIES: u32 = 5;
+
+/// Price of embedding a million tokens with
+/// [`EMBEDDING_MODEL`](crate::rag::EMBEDDING_MODEL), in US dollars
+pub const PRICE_PER_MILLION_TOKENS: f64 = 0.13;
+
+/// Most (estimated) tokens sent in a single request, leaving headroom under
+/// OpenAI's limit of 300,000 since our estimates are rough
+const MAX_BATCH_TOKENS: usize = 200_000;
+
+/// Delay before the first retry, doubling with each subsequent one
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+
+/// Longest we'll wait before retrying a rate-limited request
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(120);
+
+/// How documents are sent to the embedding model
+#[derive(Clone, Debug)]
+pub struct EmbedConfig {
+    /// Most documents sent in a single request
+    pub batch_size: usize,
+    /// Most requests in flight at once
+    pub concurrency: usize,
+    /// Most (estimated) tokens sent per minute, if limited
+    pub tokens_per_minute: Option<u64>,
+    /// Number of times a rate-limited request is retried
+    pub retries: u32,
+}
+
+impl Default for EmbedConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_EMBED_CONCURRENCY,
+            tokens_per_minute: Some(DEFAULT_TOKENS_PER_MINUTE),
+            retries: DEFAULT_EMBED_RETRIES,
+        }
+    }
+}
+
+/// What embedding a set of documents would involve
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EmbedEstimate {
+    pub documents: usize,
+    pub batches: usize,
+    /// Estimated number of tokens sent
+    pub tokens: usize,
+    /// Estimated cost in US dollars
+    pub cost: f64,
+    /// Least time the rate limit allows, if there is one
+    pub duration: Option<Duration>,
+}
+
+impl EmbedEstimate {
+    pub fn new(documents: &[WebDoc], config: &EmbedConfig) -> Self {
+        let tokens: usize =
+            documents.iter().map(|doc| estimate_tokens(&doc.text)).sum();
+
+        Self {
+            documents: documents.len(),
+            batches: batches(documents.to_vec(), config.batch_size).len(),
+            tokens,
+            cost: tokens as f64 * PRICE_PER_MILLION_TOKENS / 1_000_000.0,
+            duration: config.tokens_per_minute.map(|tpm| {
+                Duration::from_secs_f64(tokens as f64 * 60.0 / tpm as f64)
+            }),
+        }
+    }
+}
+
+impl Display for EmbedEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Would embed {} documents (~{} tokens) in {} requests, costing about ${:.4}",
+            self.documents, self.tokens, self.batches, self.cost
+        )?;
+        if let Some(duration) = self.duration {
+            write!(f, " and taking at least {}s", duration.as_secs().max(1))?;
+        }
+        Ok(())
+    }
+}
+
+/// Split `documents` into batches of at most `batch_size` documents and
+/// [`MAX_BATCH_TOKENS`] (estimated) tokens, preserving order
+pub fn batches(documents: Vec<WebDoc>, batch_size: usize) -> Vec<Vec<WebDoc>> {
+    let batch_size = batch_size.max(1);
+    let mut batches = vec![];
+    let mut current: Vec<WebDoc> = vec![];
+    let mut current_tokens = 0;
+
+    for doc in documents {
+        let tokens = estimate_tokens(&doc.text);
+        if !current.is_empty()
+            && (current.len() >= batch_size
+                || current_tokens + tokens > MAX_BATCH_TOKENS)
+        {
+            batches.push(std::mem::take(&mut current));
+            current_tokens = 0;
+        }
+        current_tokens += tokens;
+        current.push(doc);
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+
+    batches
+}
+
+/// Token bucket refilling at a steady rate up to a minute's worth of tokens
+#[derive(Debug)]
+struct RateLimiter {
+    tokens_per_minute: f64,
+    /// Tokens available and when we last topped them up
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(tokens_per_minute: u64) -> Self {
+        let tokens_per_minute = tokens_per_minute.max(1) as f64;
+        Self {
+            tokens_per_minute,
+            state: Mutex::new((tokens_per_minute, Instant::now())),
+        }
+    }
+
+    /// Wait until `tokens` can be sent without exceeding the limit
+    async fn acquire(&self, tokens: usize) {
+        /* a batch bigger than the whole budget waits for a full bucket */
+        let wanted = (tokens as f64).min(self.tokens_per_minute);
+
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let (available, last) = &mut *state;
+                let now = Instant::now();
+                *available = (*available
+                    + now.duration_since(*last).as_secs_f64()
+                        * self.tokens_per_minute
+                        / 60.0)
+                    .min(self.tokens_per_minute);
+                *last = now;
+
+                if *available >= wanted {
+                    *available -= wanted;
+                    return;
+                }
+                Duration::from_secs_f64(
+                    (wanted - *available) * 60.0 / self.tokens_per_minute,
+                )
+            };
+            sleep(wait).await;
+        }
+    }
+}
+
+/// How long to wait before retry number `attempt + 1`, if `error` is worth
+/// retrying
+///
+/// Only rate limiting is retried. OpenAI says how long to wait in the error
+/// message ("Please try again in 1.5s"), which we honour if we can find it;
+/// otherwise backoff is exponential.
+fn retry_delay(error: &EmbeddingError, attempt: u32) -> Option<Duration> {
+    let EmbeddingError::ProviderError(message) = error else {
+        return None;
+    };
+    let lowered = message.to_lowercase();
+    if !lowered.contains("rate limit") && !lowered.contains("rate_limit") {
+        return None;
+    }
+
+    let hinted = Regex::new(r"try again in (\d+(?:\.\d+)?)(ms|s)")
+        .expect("valid regex")
+        .captures(&lowered)
+        .and_then(|caps| {
+            let n: f64 = caps[1].parse().ok()?;
+            Some(Duration::from_secs_f64(match &caps[2] {
+                "ms" => n / 1000.0,
+                _ => n,
+            }))
+        });
+
+    Some(
+        hinted
+            .unwrap_or_else(|| {
+                BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt))
+            })
+            .min(MAX_RETRY_WAIT),
+    )
+}
+
+/// Embed `documents` with `model` in batches, respecting the rate limit and
+/// retrying rate-limited requests
+///
+/// Results are in the same order as `documents`.
+pub async fn embed_batched<M: EmbeddingModel>(
+    model: &M,
+    documents: Vec<WebDoc>,
+    config: &EmbedConfig,
+) -> eyre::Result<Vec<(WebDoc, OneOrMany<Embedding>)>> {
+    let total = documents.len();
+    let batches = batches(documents, config.batch_size.min(M::MAX_DOCUMENTS));
+    let count = batches.len();
+    let limiter = config.tokens_per_minute.map(RateLimiter::new);
+    let done = AtomicUsize::new(0);
+
+    let embedded: Vec<Vec<(WebDoc, OneOrMany<Embedding>)>> =
+        stream::iter(batches.into_iter().enumerate())
+            .map(|(i, batch)| {
+                let limiter = &limiter;
+                let done = &done;
+                async move {
+                    let texts: Vec<String> =
+                        batch.iter().map(|doc| doc.text.clone()).collect();
+                    let tokens = texts.iter().map(|t| estimate_tokens(t)).sum();
+
+                    let mut attempt = 0;
+                    let embeddings = loop {
+                        if let Some(limiter) = limiter {
+                            limiter.acquire(tokens).await;
+                        }
+                        match model.embed_texts(texts.clone()).await {
+                            Ok(embeddings) => break embeddings,
+                            Err(e) => match retry_delay(&e, attempt) {
+                                Some(delay) if attempt < config.retries => {
+                                    attempt += 1;
+                                    warn!(
+                                        "Embedding batch {}/{count} was rate limited; retrying in {:.1}s (attempt {attempt}/{})",
+                                        i + 1,
+                                        delay.as_secs_f64(),
+                                        config.retries
+                                    );
+                                    sleep(delay).await;
+                                }
+                                _ => {
+                                    return Err(e).wrap_err_with(|| {
+                                        format!(
+                                            "failed to embed batch {}/{count}",
+                                            i + 1
+                                        )
+                                    });
+                                }
+                            },
+                        }
+                    };
+                    if embeddings.len() != batch.len() {
+                        eyre::bail!(
+                            "expected {} embeddings for batch {}/{count} but got {}",
+                            batch.len(),
+                            i + 1,
+                            embeddings.len()
+                        );
+                    }
+
+                    let so_far =
+                        done.fetch_add(batch.len(), Ordering::Relaxed)
+                            + batch.len();
+                    info!("Embedded {so_far}/{total} documents");
+                    Ok(batch
+                        .into_iter()
+                        .zip(embeddings.into_iter().map(OneOrMany::one))
+                        .collect())
+                }
+            })
+            .buffered(config.concurrency.max(1))
+            .try_collect()
+            .await?;
+
+    Ok(embedded.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{provider::HashEmbedder, search::TextPosition};
+    use rig::client::Nothing;
+    use url::Url;
+
+    fn doc(text: &str) -> WebDoc {
+        WebDoc {
+            url: Url::parse("https://x.com").unwrap(),
+            text: text.to_string(),
+            position: TextPosition { line: 1, column: 1 },
+            anchor: None,
+            kind: Default::default(),
+            seed: None,
+            bookmarked: None,
+            published: None,
+            archived: None,
+        }
+    }
+
+    #[test]
+    fn test_batches() {
+        let docs: Vec<WebDoc> = (0..5).map(|i| doc(&i.to_string())).collect();
+        let sizes: Vec<usize> =
+            batches(docs.clone(), 2).iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(batches(docs, 0).len(), 5);
+
+        /* big documents are split by tokens as well as count */
+        let big = "word ".repeat(MAX_BATCH_TOKENS * 4 / 5 / 2);
+        let docs = vec![doc(&big), doc(&big), doc(&big)];
+        let sizes: Vec<usize> =
+            batches(docs, 100).iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let docs = vec![doc(&"a".repeat(4000)), doc(&"b".repeat(4000))];
+        let config = EmbedConfig {
+            batch_size: 1,
+            tokens_per_minute: Some(1200),
+            ..Default::default()
+        };
+        let estimate = EmbedEstimate::new(&docs, &config);
+        assert_eq!(estimate.documents, 2);
+        assert_eq!(estimate.batches, 2);
+        assert_eq!(estimate.tokens, 2000);
+        assert!((estimate.cost - 0.00026).abs() < 1e-9);
+        assert_eq!(estimate.duration, Some(Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let limited = EmbeddingError::ProviderError(
+            "Rate limit reached for text-embedding-3-large on tokens per min (TPM): Limit 1000000, Used 999000, Requested 5000. Please try again in 240ms.".to_string(),
+        );
+        assert_eq!(retry_delay(&limited, 0), Some(Duration::from_millis(240)));
+
+        let unhinted = EmbeddingError::ProviderError(
+            r#"{"error": {"type": "rate_limit_exceeded"}}"#.to_string(),
+        );
+        assert_eq!(retry_delay(&unhinted, 2), Some(BACKOFF_BASE * 4));
+        assert_eq!(retry_delay(&unhinted, 20), Some(MAX_RETRY_WAIT));
+
+        let invalid = EmbeddingError::ProviderError(
+            "Invalid API key provided".to_string(),
+        );
+        assert_eq!(retry_delay(&invalid, 0), None);
+    }
+
+    /// Embedder that's rate limited on its first `failures` requests
+    #[derive(Clone, Default)]
+    struct Flaky {
+        failures: std::sync::Arc<AtomicUsize>,
+        calls: std::sync::Arc<AtomicUsize>,
+    }
+
+    impl EmbeddingModel for Flaky {
+        const MAX_DOCUMENTS: usize = 2;
+
+        type Client = Nothing;
+
+        fn make(_: &Nothing, _: impl Into<String>, _: Option<usize>) -> Self {
+            Self::default()
+        }
+
+        fn ndims(&self) -> usize {
+            HashEmbedder::default().ndims
+        }
+
+        async fn embed_texts(
+            &self,
+            texts: impl IntoIterator<Item = String>,
+        ) -> Result<Vec<Embedding>, EmbeddingError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failing = self.failures.fetch_update(
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+                |n| n.checked_sub(1),
+            );
+            if failing.is_ok() {
+                return Err(EmbeddingError::ProviderError(
+                    "Rate limit reached. Please try again in 1ms.".to_string(),
+                ));
+            }
+            HashEmbedder::default()
+                .embed_texts(texts.into_iter().collect::<Vec<_>>())
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_embed_batched() {
+        let docs: Vec<WebDoc> =
+            (0..5).map(|i| doc(&format!("document {i}"))).collect();
+        let flaky = Flaky {
+            failures: std::sync::Arc::new(AtomicUsize::new(2)),
+            calls: Default::default(),
+        };
+        /* batches are capped at the model's MAX_DOCUMENTS */
+        let config = EmbedConfig {
+            batch_size: 100,
+            ..Default::default()
+        };
+
+        let embedded =
+            embed_batched(&flaky, docs.clone(), &config).await.unwrap();
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 3 + 2);
+        assert_eq!(
+            embedded.iter().map(|(doc, _)| doc).collect::<Vec<_>>(),
+            docs.iter().collect::<Vec<_>>()
+        );
+        for (doc, embedding) in &embedded {
+            assert_eq!(embedding.first().document, doc.text);
+            assert_eq!(
+                embedding.first().vec,
+                HashEmbedder::default().vector(&doc.text)
+            );
+        }
+
+        /* giving up once retries run out */
+        flaky.failures.store(10, Ordering::SeqCst);
+        let config = EmbedConfig {
+            retries: 1,
+            ..config
+        };
+        assert!(embed_batched(&flaky, docs, &config).await.is_err());
+    }
+}
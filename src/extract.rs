@@ -1,58 +1,348 @@
 use eyre::Result;
-use scraper::{ElementRef, Html, Selector};
+use scraper::{ElementRef, Html, Node, Selector};
 use std::collections::HashSet;
 use url::Url;
 
-use crate::rag::WebDoc;
+use crate::{
+    rag::{ContentKind, WebDoc},
+    search::TextPosition,
+};
 
+/// Extract the text of a page using generic, site-agnostic heuristics
 pub fn extract_text(url: Url, html: &str) -> Result<Vec<WebDoc>> {
     let document = Html::parse_document(html);
+    let mut docs = DocBuilder::new(url);
+    docs.push_blocks(document.root_element());
+    Ok(docs.finish())
+}
 
-    // Single selector so we preserve DOM order.
-    let block_sel =
-        Selector::parse("p,li,blockquote,pre,code,h1,h2,h3,h4,h5,h6").unwrap();
-
-    let mut out = Vec::new();
-    let mut seen = HashSet::<String>::new();
+/// Accumulates the [`WebDoc`]s of a single page
+///
+/// Takes care of numbering blocks, deduplicating repeated text and tracking
+/// the nearest anchor so that site-specific extractors only need to decide
+/// *which* elements to read.
+pub struct DocBuilder {
+    url: Url,
+    out: Vec<WebDoc>,
+    seen: HashSet<String>,
+    last_heading_anchor: Option<String>,
+}
 
-    for node in document.select(&block_sel) {
-        if is_boilerplate(node) {
-            continue;
-        }
-        if is_nested_block(node) {
-            continue;
+impl DocBuilder {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            out: Vec::new(),
+            seen: HashSet::new(),
+            last_heading_anchor: None,
         }
+    }
 
-        let text = normalize_text(node.text());
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Append a single block of already-normalised text
+    pub fn push(
+        &mut self,
+        text: String,
+        kind: ContentKind,
+        anchor: Option<String>,
+    ) {
         if text.is_empty() {
-            continue;
+            return;
         }
 
         // Dedup identical paragraphs (common with nested blocks / repeated chrome).
-        if !seen.insert(text.clone()) {
-            continue;
+        if !self.seen.insert(text.clone()) {
+            return;
         }
 
-        out.push(WebDoc {
-            url: url.clone(),
+        self.out.push(WebDoc {
+            url: self.url.clone(),
             text,
+            position: TextPosition {
+                line: self.out.len() + 1,
+                column: 1,
+            },
+            anchor: anchor.or_else(|| self.last_heading_anchor.clone()),
+            kind,
+            seed: None,
+            bookmarked: None,
+            published: None,
+            archived: None,
         });
     }
 
-    Ok(out)
+    /// Append the entire text of `el` as a single prose block
+    ///
+    /// Site-specific extractors know the page's structure, so any ancestor's
+    /// `id` (such as a comment's row) is trusted as an anchor.
+    pub fn push_element(&mut self, el: ElementRef<'_>) {
+        self.push(element_text(el), ContentKind::Prose, closest_id(el));
+    }
+
+    /// Append every block found beneath `root`
+    ///
+    /// Boilerplate detection only considers elements up to `root`, so callers
+    /// that already know where the content lives aren't tripped up by
+    /// chrome-looking containers around it. Returns the number of blocks
+    /// added.
+    pub fn push_blocks(&mut self, root: ElementRef<'_>) -> usize {
+        // Single selector so we preserve DOM order.
+        let block_sel = Selector::parse(
+            "p,li,blockquote,pre,code,h1,h2,h3,h4,h5,h6,figcaption,table,dl,td,th",
+        )
+        .unwrap();
+
+        let before = self.out.len();
+
+        for node in root.select(&block_sel) {
+            if is_boilerplate(node, root) {
+                continue;
+            }
+            if is_nested_block(node, root) {
+                continue;
+            }
+            // Layout tables aren't blocks themselves; their cells are read as
+            // prose unless they hold blocks of their own.
+            match node.value().name() {
+                "table" if !is_data_table(node) => continue,
+                "td" | "th"
+                    if owning_table(node).is_none_or(is_data_table)
+                        || has_block_content(node) =>
+                {
+                    continue;
+                }
+                _ => {}
+            }
+
+            if is_heading(node.value().name()) {
+                self.last_heading_anchor = nearest_anchor(node);
+            }
+
+            // Prefer the block's own id, then its section's heading, and only
+            // then the section element it sits in.
+            let anchor = node
+                .value()
+                .id()
+                .map(str::to_string)
+                .or_else(|| self.last_heading_anchor.clone())
+                .or_else(|| nearest_anchor(node));
+
+            let (kind, texts) = match node.value().name() {
+                "table" => (ContentKind::Tabular, table_rows(node)),
+                "dl" => (ContentKind::Tabular, definitions(node)),
+                _ => (ContentKind::Prose, vec![element_text(node)]),
+            };
+
+            for text in texts {
+                self.push(text, kind, anchor.clone());
+            }
+        }
+
+        self.out.len() - before
+    }
+
+    /// Like [`DocBuilder::push_blocks`], but falls back to the whole text of
+    /// `root` if it contains no recognisable blocks
+    pub fn push_blocks_or_element(&mut self, root: ElementRef<'_>) {
+        if self.push_blocks(root) == 0 {
+            self.push_element(root);
+        }
+    }
+
+    pub fn finish(self) -> Vec<WebDoc> {
+        self.out
+    }
+}
+
+/// Ancestors of `node` strictly between it and `root`
+fn ancestors_within<'a>(
+    node: ElementRef<'a>,
+    root: ElementRef<'a>,
+) -> impl Iterator<Item = ElementRef<'a>> {
+    node.ancestors()
+        .take_while(move |anc| anc.id() != root.id())
+        .filter_map(ElementRef::wrap)
 }
 
-fn is_nested_block(node: ElementRef<'_>) -> bool {
+fn is_nested_block(node: ElementRef<'_>, root: ElementRef<'_>) -> bool {
     // If an ancestor is also a "block" tag we extract, skip this node to avoid duplicates like:
     // <pre><code>...</code></pre>
-    for anc in node.ancestors().skip(1) {
-        if let Some(el) = ElementRef::wrap(anc)
-            && is_block_tag(el.value().name())
-        {
-            return true;
+    ancestors_within(node, root)
+        .skip(1)
+        .any(|el| is_extracted_block(el))
+}
+
+/// Whether `el` is extracted whole, so nothing beneath it needs extracting
+fn is_extracted_block(el: ElementRef<'_>) -> bool {
+    match el.value().name() {
+        "table" => is_data_table(el),
+        tag => is_block_tag(tag),
+    }
+}
+
+/// Whether any element beneath `el` is a block we'd extract
+fn has_block_content(el: ElementRef<'_>) -> bool {
+    el.descendants()
+        .skip(1)
+        .filter_map(ElementRef::wrap)
+        .any(|d| is_block_tag(d.value().name()))
+}
+
+/// Whether `table` holds data rather than laying out the page
+///
+/// Tables with header cells are data. Otherwise, tables whose cells contain
+/// paragraphs, lists, headings or further tables are taken to be layout, as
+/// on many older sites.
+fn is_data_table(table: ElementRef<'_>) -> bool {
+    let header_sel = Selector::parse("th,thead").unwrap();
+    let layout_sel =
+        Selector::parse("p,ul,ol,li,blockquote,pre,h1,h2,h3,h4,h5,h6,table,dl")
+            .unwrap();
+
+    table
+        .select(&header_sel)
+        .any(|el| owning_table(el) == Some(table))
+        || !table.select(&layout_sel).any(|el| el.id() != table.id())
+}
+
+/// Flatten a table into one line of text per row
+///
+/// Rows are rendered as `header: value` pairs when the table has a header row,
+/// as `header: values` when the row leads with its own header cell and as
+/// ` | `-separated cells otherwise. The caption, if any, comes first.
+fn table_rows(table: ElementRef<'_>) -> Vec<String> {
+    let caption_sel = Selector::parse("caption").unwrap();
+    let row_sel = Selector::parse("tr").unwrap();
+
+    let mut out: Vec<String> = table
+        .select(&caption_sel)
+        .filter(|c| owning_table(*c) == Some(table))
+        .map(|c| element_text(c))
+        .collect();
+
+    let rows: Vec<Vec<(bool, String)>> = table
+        .select(&row_sel)
+        .filter(|r| owning_table(*r) == Some(table))
+        .map(|r| {
+            r.children()
+                .filter_map(ElementRef::wrap)
+                .filter(|c| matches!(c.value().name(), "td" | "th"))
+                .map(|c| (c.value().name() == "th", element_text(c)))
+                .collect()
+        })
+        .filter(|cells: &Vec<_>| !cells.is_empty())
+        .collect();
+
+    let mut headers: Option<Vec<String>> = None;
+
+    for row in rows {
+        if row.iter().all(|(is_header, _)| *is_header) {
+            headers = Some(row.into_iter().map(|(_, text)| text).collect());
+            continue;
         }
+
+        let line = match &headers {
+            Some(headers) => row
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, value))| !value.is_empty())
+                .map(|(i, (_, value))| match headers.get(i) {
+                    Some(header) if !header.is_empty() => {
+                        format!("{header}: {value}")
+                    }
+                    _ => value.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join("; "),
+            None => {
+                let values = row
+                    .iter()
+                    .skip_while(|(is_header, _)| *is_header)
+                    .map(|(_, value)| value.as_str())
+                    .filter(|value| !value.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" | ");
+
+                // Key/value tables (e.g., infoboxes) lead each row with a <th>.
+                match row.first() {
+                    Some((true, header)) if !header.is_empty() => {
+                        format!("{header}: {values}")
+                    }
+                    _ => values,
+                }
+            }
+        };
+        out.push(line);
     }
-    false
+
+    out
+}
+
+/// Closest `<table>` ancestor of the node
+fn owning_table(node: ElementRef<'_>) -> Option<ElementRef<'_>> {
+    node.ancestors()
+        .filter_map(ElementRef::wrap)
+        .find(|el| el.value().name() == "table")
+}
+
+/// Flatten a definition list into one `term: definition` line per term
+fn definitions(dl: ElementRef<'_>) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut terms: Vec<String> = Vec::new();
+    let mut defs: Vec<String> = Vec::new();
+
+    let mut flush = |terms: &mut Vec<String>, defs: &mut Vec<String>| {
+        if !terms.is_empty() || !defs.is_empty() {
+            out.push(format!("{}: {}", terms.join(", "), defs.join(" ")));
+        }
+        terms.clear();
+        defs.clear();
+    };
+
+    // Terms and definitions may be wrapped in <div>s per the HTML spec.
+    for el in dl.descendants().filter_map(ElementRef::wrap) {
+        match el.value().name() {
+            "dt" => {
+                if !defs.is_empty() {
+                    flush(&mut terms, &mut defs);
+                }
+                terms.push(element_text(el));
+            }
+            "dd" => defs.push(element_text(el)),
+            _ => {}
+        }
+    }
+    flush(&mut terms, &mut defs);
+
+    out
+}
+
+/// `id` of the node itself or of its closest ancestor that has one
+fn closest_id(node: ElementRef<'_>) -> Option<String> {
+    std::iter::once(node)
+        .chain(node.ancestors().filter_map(ElementRef::wrap))
+        .find_map(|el| el.value().id().map(str::to_string))
+}
+
+/// `id` of the node itself or of its closest enclosing section or heading
+/// that has one
+///
+/// Other ancestors' ids tend to belong to page-wide wrappers (`#content`,
+/// `#app`) that are no use for jumping to the text.
+fn nearest_anchor(node: ElementRef<'_>) -> Option<String> {
+    std::iter::once(node)
+        .chain(node.ancestors().filter_map(ElementRef::wrap).filter(|el| {
+            let tag = el.value().name();
+            is_heading(tag) || matches!(tag, "section" | "article")
+        }))
+        .find_map(|el| el.value().id().map(str::to_string))
+}
+
+fn is_heading(tag: &str) -> bool {
+    matches!(tag, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
 }
 
 fn is_block_tag(tag: &str) -> bool {
@@ -68,42 +358,43 @@ fn is_block_tag(tag: &str) -> bool {
             | "h4"
             | "h5"
             | "h6"
+            | "figcaption"
+            | "table"
+            | "dl"
     )
 }
 
-fn is_boilerplate(node: ElementRef<'_>) -> bool {
+fn is_boilerplate(node: ElementRef<'_>, root: ElementRef<'_>) -> bool {
     // Drop anything inside obvious chrome containers or with obvious chrome-y attributes.
-    for anc in node.ancestors() {
-        if let Some(el) = ElementRef::wrap(anc) {
-            let tag = el.value().name();
+    for el in ancestors_within(node, root) {
+        let tag = el.value().name();
 
-            if matches!(tag, "nav" | "header" | "footer" | "aside") {
-                return true;
-            }
+        if matches!(tag, "nav" | "header" | "footer" | "aside") {
+            return true;
+        }
 
-            if let Some(role) = el.value().attr("role")
-                && role.eq_ignore_ascii_case("navigation")
-            {
-                return true;
-            }
+        if let Some(role) = el.value().attr("role")
+            && role.eq_ignore_ascii_case("navigation")
+        {
+            return true;
+        }
 
-            if let Some(v) = el.value().attr("aria-hidden")
-                && v.eq_ignore_ascii_case("true")
-            {
-                return true;
-            }
+        if let Some(v) = el.value().attr("aria-hidden")
+            && v.eq_ignore_ascii_case("true")
+        {
+            return true;
+        }
 
-            if let Some(id) = el.value().attr("id")
-                && looks_like_chrome(id)
-            {
-                return true;
-            }
+        if let Some(id) = el.value().attr("id")
+            && looks_like_chrome(id)
+        {
+            return true;
+        }
 
-            if let Some(class) = el.value().attr("class")
-                && looks_like_chrome(class)
-            {
-                return true;
-            }
+        if let Some(class) = el.value().attr("class")
+            && looks_like_chrome(class)
+        {
+            return true;
         }
     }
     false
@@ -130,7 +421,62 @@ fn looks_like_chrome(s: &str) -> bool {
     BAD.iter().any(|k| s.contains(k))
 }
 
-fn normalize_text<'a>(iter: impl Iterator<Item = &'a str>) -> String {
+/// Text of `el` with whitespace normalized, keeping the words either side of
+/// a block boundary (e.g., `render?<p>Most`) apart
+pub fn element_text(el: ElementRef<'_>) -> String {
+    fn walk<'a>(el: ElementRef<'a>, pieces: &mut Vec<&'a str>) {
+        for child in el.children() {
+            match child.value() {
+                Node::Text(text) => pieces.push(text),
+                Node::Element(e) => {
+                    let breaks = breaks_text(e.name());
+                    if breaks {
+                        pieces.push(" ");
+                    }
+                    walk(
+                        ElementRef::wrap(child).expect("is an element"),
+                        pieces,
+                    );
+                    if breaks {
+                        pieces.push(" ");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut pieces = Vec::new();
+    walk(el, &mut pieces);
+    normalize_text(pieces.into_iter())
+}
+
+/// Whether the element starts a new line when rendered
+fn breaks_text(tag: &str) -> bool {
+    is_block_tag(tag)
+        || matches!(
+            tag,
+            "address"
+                | "article"
+                | "br"
+                | "caption"
+                | "dd"
+                | "div"
+                | "dt"
+                | "footer"
+                | "header"
+                | "hr"
+                | "main"
+                | "ol"
+                | "section"
+                | "td"
+                | "th"
+                | "tr"
+                | "ul"
+        )
+}
+
+pub fn normalize_text<'a>(iter: impl Iterator<Item = &'a str>) -> String {
     let mut s = String::new();
     let mut last_was_space = false;
 
@@ -150,3 +496,144 @@ fn normalize_text<'a>(iter: impl Iterator<Item = &'a str>) -> String {
 
     s.trim().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(html: &str) -> Vec<(ContentKind, String)> {
+        extract_text(Url::parse("https://x.com").unwrap(), html)
+            .unwrap()
+            .into_iter()
+            .map(|doc| (doc.kind, doc.text))
+            .collect()
+    }
+
+    fn first(html: &str, tag: &str) -> Vec<String> {
+        let document = Html::parse_document(html);
+        let el = document
+            .select(&Selector::parse(tag).unwrap())
+            .next()
+            .unwrap();
+        match tag {
+            "table" => table_rows(el),
+            _ => definitions(el),
+        }
+    }
+
+    #[test]
+    fn test_element_text() {
+        let document = Html::parse_fragment(
+            "<div>Does it <b>re</b>nder?<p>Most  are\nSPAs.</p>Yes<br>No<ul><li>one</li><li>two</li></ul></div>",
+        );
+        let div = document
+            .select(&Selector::parse("div").unwrap())
+            .next()
+            .unwrap();
+        assert_eq!(
+            element_text(div),
+            "Does it render? Most are SPAs. Yes No one two"
+        );
+    }
+
+    #[test]
+    fn test_anchors() {
+        let html = r#"<html><body><div id="app"><main id="content">
+            <p>Before any heading.</p>
+            <section id="install"><div id="wrapper"><p>Run the installer.</p></div></section>
+            <h2 id="usage">Usage</h2><p>Call it.</p>
+            <p id="note">Mind the gap.</p>
+            </main></div></body></html>"#;
+        let url = Url::parse("https://x.com/").unwrap();
+        let anchors: Vec<(String, Option<String>)> = extract_text(url, html)
+            .unwrap()
+            .into_iter()
+            .map(|doc| (doc.text, doc.anchor))
+            .collect();
+        let anchor = |s: &str| Some(s.to_string());
+
+        assert_eq!(
+            anchors,
+            vec![
+                ("Before any heading.".to_string(), None),
+                ("Run the installer.".to_string(), anchor("install")),
+                ("Usage".to_string(), anchor("usage")),
+                ("Call it.".to_string(), anchor("usage")),
+                ("Mind the gap.".to_string(), anchor("note")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_table_rows() {
+        let header_row = "<table><caption>Releases</caption>\
+            <thead><tr><th>Version</th><th>Date</th><th></th></tr></thead>\
+            <tr><td>1.2</td><td>2025-01-02</td><td>latest</td></tr>\
+            <tr><td>1.1</td><td></td><td></td></tr></table>";
+        assert_eq!(
+            first(header_row, "table"),
+            vec![
+                "Releases",
+                "Version: 1.2; Date: 2025-01-02; latest",
+                "Version: 1.1",
+            ]
+        );
+
+        let infobox = "<table><tr><th>Born</th><td>1815</td></tr>\
+            <tr><th>Known for</th><td>Analytical Engine</td><td>notes</td></tr>\
+            <tr><td>plain</td><td>row</td></tr></table>";
+        assert_eq!(
+            first(infobox, "table"),
+            vec![
+                "Born: 1815",
+                "Known for: Analytical Engine | notes",
+                "plain | row"
+            ]
+        );
+
+        /* rows of nested tables belong to the nested table */
+        let nested = "<table><tr><td>outer<table><tr><td>inner</td></tr></table></td></tr></table>";
+        assert_eq!(first(nested, "table"), vec!["outer inner"]);
+    }
+
+    #[test]
+    fn test_definitions() {
+        let dl = "<dl><dt>TTL</dt><dd>Time to live</dd>\
+            <div><dt>RTT</dt><dt>Ping</dt><dd>Round trip</dd><dd>time</dd></div>\
+            <dd>orphan</dd></dl>";
+        assert_eq!(
+            first(dl, "dl"),
+            vec!["TTL: Time to live", "RTT, Ping: Round trip time orphan"]
+        );
+    }
+
+    #[test]
+    fn test_data_and_layout_tables() {
+        let data = "<main><table><tr><td>GET</td><td>Read</td></tr>\
+            <tr><td>PUT</td><td>Replace</td></tr></table></main>";
+        assert_eq!(
+            blocks(data),
+            vec![
+                (ContentKind::Tabular, "GET | Read".to_string()),
+                (ContentKind::Tabular, "PUT | Replace".to_string()),
+            ]
+        );
+
+        let layout = "<main><table><tr>\
+            <td>Welcome to my home page</td>\
+            <td><h2>Essays</h2><p>First paragraph.</p><p>Second paragraph.</p>\
+            <table><tr><td>Inner cell</td><td><p>Inner paragraph.</p></td></tr></table>\
+            </td></tr></table></main>";
+        assert_eq!(
+            blocks(layout),
+            vec![
+                (ContentKind::Prose, "Welcome to my home page".to_string()),
+                (ContentKind::Prose, "Essays".to_string()),
+                (ContentKind::Prose, "First paragraph.".to_string()),
+                (ContentKind::Prose, "Second paragraph.".to_string()),
+                (ContentKind::Prose, "Inner cell".to_string()),
+                (ContentKind::Prose, "Inner paragraph.".to_string()),
+            ]
+        );
+    }
+}
@@ -1,12 +1,378 @@
 use eyre::WrapErr;
 use futures::{StreamExt, stream};
-use std::{sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
 use tokio::task::LocalSet;
-use tokio::time::{Duration, timeout};
-use tracing::{debug, warn};
+use tokio::time::{Duration, sleep, timeout};
+use tracing::{debug, info, warn};
 use url::Url;
 
-use playwright::api::{Browser, BrowserContext, Page, Playwright};
+use playwright::api::{Browser, BrowserContext, Page, Playwright, Response};
+use scraper::{Html, Selector};
+
+use crate::{
+    block::{BlockConfig, BlockedCounts},
+    http::{self, ProxyConfig},
+    robots::RobotsCache,
+    session::SessionConfig,
+    settle::{self, SettleConfig},
+};
+
+/// Default time allowed for a single attempt at fetching a page
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Default number of times a rate-limited or failing request is retried
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubling with each subsequent one
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+
+/// Longest we'll wait before retrying, whether backing off or told to by
+/// `Retry-After` (longer requests are capped to this)
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(120);
+
+/// Most redirects followed when fetching a page without the browser
+const MAX_REDIRECTS: usize = 10;
+
+/// Failures that callers may want to tell apart from generic fetch errors
+#[derive(Debug)]
+pub enum FetchError {
+    /// The page didn't load within the per-URL timeout
+    Timeout(Duration),
+    /// `robots.txt` forbids us from fetching the page
+    RobotsDisallowed,
+    /// The server responded with a 4xx or 5xx status
+    Status {
+        status: u16,
+        /// Value of the `Retry-After` header, if any
+        retry_after: Option<Duration>,
+    },
+}
+
+impl FetchError {
+    /// How long to wait before retry number `attempt + 1`, if we should retry
+    ///
+    /// Only rate limiting (429) and server errors (5xx) are retried. Backoff
+    /// is exponential unless the server asked for a specific delay, and
+    /// either way is capped at [`MAX_RETRY_WAIT`].
+    fn retry_delay(&self, attempt: u32) -> Option<Duration> {
+        match self {
+            Self::Status {
+                status,
+                retry_after,
+            } if *status == 429 || *status >= 500 => {
+                let delay = retry_after.unwrap_or_else(|| {
+                    BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt))
+                });
+                Some(delay.min(MAX_RETRY_WAIT))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout(after) => write!(f, "timeout after {after:?}"),
+            Self::RobotsDisallowed => write!(f, "disallowed by robots.txt"),
+            Self::Status { status, .. } => write!(f, "HTTP status {status}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+/// Settings for [`fetch_all_html`]
+#[derive(Clone)]
+pub struct FetchOptions {
+    /// Maximum number of pages open at once
+    pub concurrency: usize,
+    /// Consulted before every request; `None` ignores `robots.txt` entirely
+    pub robots: Option<Arc<RobotsCache>>,
+    /// Time allowed for each attempt at fetching a page
+    pub per_url_timeout: Duration,
+    /// Number of times to retry after a 429 or 5xx response
+    pub retries: u32,
+    /// Subresources the browser shouldn't bother loading
+    pub blocking: BlockConfig,
+    /// What to wait for before reading each page
+    pub settle: SettleConfig,
+    /// Cookies and headers to fetch pages with
+    pub session: SessionConfig,
+    /// Fetch pages that get headers restricted to their hosts with
+    /// [`DirectFetcher`] rather than the browser
+    pub direct: bool,
+    /// Proxy the browser connects through
+    pub proxy: ProxyConfig,
+}
+
+/// Bandwidth accounting across every page an [`HtmlFetcher`] retrieves
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TransferStats {
+    /// Bytes actually transferred, per the Resource Timing API
+    pub transferred: u64,
+    /// Subresources that weren't loaded due to blocking, counted from each
+    /// page's markup rather than observed
+    pub blocked: BlockedCounts,
+}
+
+/// Sums the transfer sizes of the document and everything it loaded
+const TRANSFER_SIZE_JS: &str = r#"() => performance
+    .getEntriesByType("navigation")
+    .concat(performance.getEntriesByType("resource"))
+    .reduce((n, e) => n + (e.transferSize || 0), 0)"#;
+
+/// A page as retrieved by [`HtmlFetcher::fetch_html`]
+#[derive(Clone, Debug)]
+pub struct FetchedPage {
+    /// Where we ended up after following any redirects
+    pub url: Url,
+    /// URLs redirected through on the way to `url`, starting with the one
+    /// requested (empty if there were no redirects)
+    pub redirects: Vec<Url>,
+    pub html: String,
+}
+
+impl FetchedPage {
+    /// URL identifying this page's content
+    ///
+    /// This is the page's `<link rel="canonical">`, provided it's on the same
+    /// host (so a misconfigured site can't claim every page is its homepage
+    /// on some other domain), and otherwise where we ended up.
+    pub fn canonical_url(&self) -> Url {
+        let document = Html::parse_document(&self.html);
+        let sel = Selector::parse("link[rel~=canonical][href]").unwrap();
+
+        document
+            .select(&sel)
+            .filter_map(|link| self.url.join(link.value().attr("href")?).ok())
+            .find(|canonical| canonical.host_str() == self.url.host_str())
+            .map(|mut canonical| {
+                canonical.set_fragment(None);
+                canonical
+            })
+            .unwrap_or_else(|| self.url.clone())
+    }
+}
+
+/// Final URL of a navigation to `requested` and the redirects on the way
+///
+/// HTTP redirects are recorded by the browser against the response, whereas
+/// client-side ones (via JavaScript or `<meta http-equiv="refresh">`) only
+/// show up in the page's URL once it's settled.
+fn redirects(
+    requested: &Url,
+    response: Option<&Response>,
+    page: &Page,
+) -> (Url, Vec<Url>) {
+    let parse = |url: Result<String, _>| url.ok()?.parse::<Url>().ok();
+
+    let mut chain = Vec::new();
+    if let Some(response) = response {
+        let mut request = response.request().redirected_from().ok().flatten();
+        while let Some(from) = request {
+            chain.extend(parse(from.url()));
+            request = from.redirected_from().ok().flatten();
+        }
+        chain.reverse();
+        chain.extend(parse(response.url()));
+    }
+    if chain.first() != Some(requested) {
+        chain.insert(0, requested.clone());
+    }
+
+    let last = chain.last().cloned().unwrap_or_else(|| requested.clone());
+    let final_url = parse(page.url())
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .unwrap_or(last);
+
+    chain.dedup();
+    if chain.last() == Some(&final_url) {
+        chain.pop();
+    }
+
+    (final_url, chain)
+}
+
+/// Read a `file://` URL straight from disk rather than via the browser
+///
+/// HTML files are used as is, while anything else is treated as plain text.
+/// PDFs aren't supported yet.
+async fn read_local_file(url: &Url) -> eyre::Result<FetchedPage> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| eyre::eyre!("{url} isn't a local path"))?;
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    if extension.as_deref() == Some("pdf") {
+        eyre::bail!("PDF files aren't supported yet: {}", path.display());
+    }
+
+    let bytes = tokio::fs::read(&path)
+        .await
+        .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    let contents = String::from_utf8_lossy(&bytes);
+
+    let html = match extension.as_deref() {
+        Some("html" | "htm" | "xhtml") => contents.into_owned(),
+        _ => {
+            let escaped = contents
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            format!("<html><body><pre>{escaped}</pre></body></html>")
+        }
+    };
+
+    Ok(FetchedPage {
+        url: url.clone(),
+        redirects: vec![],
+        html,
+    })
+}
+
+/// Fail if the main response of a navigation has an error status
+async fn check_status(response: &Response) -> Result<(), FetchError> {
+    let status = match response.status() {
+        Ok(status) if status >= 400 => status as u16,
+        _ => return Ok(()),
+    };
+
+    let retry_after = response
+        .headers()
+        .await
+        .ok()
+        .and_then(|headers| {
+            headers
+                .into_iter()
+                .find(|h| h.name.eq_ignore_ascii_case("retry-after"))
+        })
+        .and_then(|h| parse_retry_after(&h.value));
+
+    Err(FetchError::Status {
+        status,
+        retry_after,
+    })
+}
+
+/// Parse a `Retry-After` value, which is either delay seconds or a date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    value
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .ok()
+        .or_else(|| {
+            httpdate::parse_http_date(value)
+                .ok()?
+                .duration_since(SystemTime::now())
+                .ok()
+        })
+}
+
+/// Fetches pages with plain HTTP requests rather than the browser
+///
+/// Optionally used for hosts with headers of their own (such as
+/// credentials), which the browser would send along to every other host the
+/// page loads from. Nothing but the page itself is requested, so pages that need JavaScript
+/// to render their content won't have much to show.
+pub struct DirectFetcher {
+    client: reqwest::Client,
+    session: SessionConfig,
+}
+
+impl DirectFetcher {
+    pub fn new(options: &FetchOptions) -> eyre::Result<Self> {
+        Ok(Self {
+            client: http::page_client(
+                &options.proxy,
+                options.session.user_agent.as_deref(),
+            )?,
+            session: options.session.clone(),
+        })
+    }
+
+    /// Fetch `url`, following redirects by hand so that each hop only gets
+    /// the headers configured for its own host
+    pub async fn fetch_html(&self, url: &Url) -> eyre::Result<FetchedPage> {
+        let mut current = url.clone();
+        let mut redirects = Vec::new();
+
+        loop {
+            let mut request = self.client.get(current.clone());
+            for (name, value) in self.session.headers_for(&current) {
+                request = request.header(name, value);
+            }
+            if let Some(cookie) = self.session.cookie_header(&current) {
+                request = request.header(reqwest::header::COOKIE, cookie);
+            }
+
+            let response = request.send().await.wrap_err_with(|| {
+                format!("request for {} failed", url_brief(&current))
+            })?;
+            let status = response.status();
+
+            if status.is_redirection()
+                && let Some(location) =
+                    response.headers().get(reqwest::header::LOCATION)
+            {
+                if redirects.len() >= MAX_REDIRECTS {
+                    eyre::bail!("too many redirects from {}", url_brief(url));
+                }
+                let next = location
+                    .to_str()
+                    .ok()
+                    .and_then(|location| current.join(location).ok())
+                    .filter(|next| matches!(next.scheme(), "http" | "https"))
+                    .ok_or_else(|| {
+                        eyre::eyre!(
+                            "invalid redirect from {}",
+                            url_brief(&current)
+                        )
+                    })?;
+                redirects.push(std::mem::replace(&mut current, next));
+                continue;
+            }
+
+            if status.as_u16() >= 400 {
+                return Err(FetchError::Status {
+                    status: status.as_u16(),
+                    retry_after: response
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(parse_retry_after),
+                }
+                .into());
+            }
+
+            let html = response.text().await.wrap_err_with(|| {
+                format!("failed to read {}", url_brief(&current))
+            })?;
+            if !redirects.is_empty() {
+                debug!(
+                    "{} redirected to {}",
+                    url_brief(url),
+                    url_brief(&current)
+                );
+            }
+
+            return Ok(FetchedPage {
+                url: current,
+                redirects,
+                html,
+            });
+        }
+    }
+}
 
 /// Small helper so we can log scheme/host/path without dumping full URL (which may include secrets).
 fn url_brief(url: &Url) -> String {
@@ -28,13 +394,19 @@ pub struct HtmlFetcher {
     // Keep these alive: dropping Browser/Playwright can invalidate the context/page and make all
     // fetches fail.
     _playwright: Playwright,
-    _browser: Browser,
-    context: BrowserContext,
+    browser: Browser,
+    /// Browser contexts by [`SessionConfig::host_scope`], created as needed
+    contexts: tokio::sync::Mutex<HashMap<Vec<usize>, Arc<BrowserContext>>>,
+    blocking: BlockConfig,
+    settle: SettleConfig,
+    session: SessionConfig,
+    direct: Option<DirectFetcher>,
+    stats: Mutex<TransferStats>,
 }
 
 impl HtmlFetcher {
     #[tracing::instrument(level = "debug", name = "html_fetcher.new", skip_all)]
-    pub async fn new() -> eyre::Result<Self> {
+    pub async fn new(options: &FetchOptions) -> eyre::Result<Self> {
         let playwright = Playwright::initialize()
             .await
             .wrap_err("playwright initialize failed")?;
@@ -42,30 +414,87 @@ impl HtmlFetcher {
         // playwright 0.0.20: chromium() returns BrowserType directly
         let chromium = playwright.chromium();
 
-        let browser = chromium
-            .launcher()
-            .headless(true)
-            .launch()
-            .await
-            .wrap_err("chromium launch failed")?;
-
-        let context = browser
-            .context_builder()
-            .build()
-            .await
-            .wrap_err("browser context build failed")?;
+        let args = options.blocking.launch_args();
+        let mut launcher = chromium.launcher().headless(true).args(&args);
+        if let Some(proxy) = options.proxy.browser_settings() {
+            launcher = launcher.proxy(proxy);
+        }
+        let browser =
+            launcher.launch().await.wrap_err("chromium launch failed")?;
 
-        debug!("playwright chromium launched + context created");
+        debug!("playwright chromium launched");
 
         Ok(Self {
             _playwright: playwright,
-            _browser: browser,
-            context,
+            browser,
+            contexts: Default::default(),
+            blocking: options.blocking.clone(),
+            settle: options.settle.clone(),
+            session: options.session.clone(),
+            direct: options
+                .direct
+                .then(|| DirectFetcher::new(options))
+                .transpose()?,
+            stats: Mutex::new(TransferStats::default()),
         })
     }
 
-    async fn new_page(&self) -> eyre::Result<Page> {
-        self.context
+    pub fn stats(&self) -> TransferStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Browser context that sends the headers and user agent meant for
+    /// `url`'s host
+    ///
+    /// Playwright 0.0.20 can't intercept requests, so the headers go out
+    /// with every request the page makes, whatever its host.
+    async fn context_for(
+        &self,
+        url: &Url,
+    ) -> eyre::Result<Arc<BrowserContext>> {
+        let mut contexts = self.contexts.lock().await;
+        let scope = self.session.host_scope(url);
+        if let Some(context) = contexts.get(&scope) {
+            return Ok(context.clone());
+        }
+
+        let mut headers = self.session.headers_for(url);
+        let user_agent = headers
+            .remove("user-agent")
+            .or_else(|| self.session.user_agent.clone());
+
+        let mut builder = self.browser.context_builder();
+        if let Some(state) = self.session.storage_state() {
+            builder = builder.storage_state(state);
+        }
+        if let Some(user_agent) = &user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        let context = builder
+            .build()
+            .await
+            .wrap_err("browser context build failed")?;
+        if !headers.is_empty() {
+            context
+                .set_extra_http_headers(headers)
+                .await
+                .wrap_err("context.set_extra_http_headers failed")?;
+        }
+        context
+            .add_init_script(settle::NETWORK_TRACKER_JS)
+            .await
+            .wrap_err("context.add_init_script failed")?;
+        debug!("browser context created for {}", url_brief(url));
+
+        let context = Arc::new(context);
+        contexts.insert(scope, context.clone());
+        Ok(context)
+    }
+
+    /// Open a page for fetching `url`
+    async fn new_page(&self, url: &Url) -> eyre::Result<Page> {
+        self.context_for(url)
+            .await?
             .new_page()
             .await
             .wrap_err("context.new_page failed")
@@ -77,10 +506,19 @@ impl HtmlFetcher {
         skip_all,
         fields(url = %url)
     )]
-    pub async fn fetch_html(&self, url: Url) -> eyre::Result<String> {
+    pub async fn fetch_html(&self, url: Url) -> eyre::Result<FetchedPage> {
         let started = Instant::now();
         debug!("fetch start: {}", url_brief(&url));
 
+        if let Some(direct) = &self.direct
+            && self.session.has_host_headers(&url)
+        {
+            debug!("fetching {} without the browser", url_brief(&url));
+            let page = direct.fetch_html(&url).await?;
+            self.stats.lock().unwrap().transferred += page.html.len() as u64;
+            return Ok(page);
+        }
+
         // Helper: always close pages with the correct signature for playwright 0.0.20.
         async fn close_page(page: &Page, url: &Url) {
             if let Err(e) = page.close(None).await {
@@ -88,7 +526,7 @@ impl HtmlFetcher {
             }
         }
 
-        let mut page = self.new_page().await?;
+        let mut page = self.new_page(&url).await?;
 
         // 1) First try: DOMContentLoaded (usually best for SPAs; avoids NetworkIdle hangs).
         let goto_res = page
@@ -97,46 +535,50 @@ impl HtmlFetcher {
             .goto()
             .await;
 
-        if let Err(e) = goto_res {
-            warn!(
-                "goto(DomContentLoaded) failed for {}: {:?}; retrying with Load",
-                url_brief(&url),
-                e
-            );
+        let mut response = match goto_res {
+            Ok(response) => response,
+            Err(e) => {
+                warn!(
+                    "goto(DomContentLoaded) failed for {}: {:?}; retrying with Load",
+                    url_brief(&url),
+                    e
+                );
 
-            // If the underlying target/tab vanished, the Page handle is no longer usable.
-            if is_object_not_found(&e) {
-                close_page(&page, &url).await;
-                page = self.new_page().await?;
-            }
+                // If the underlying target/tab vanished, the Page handle is no longer usable.
+                if is_object_not_found(&e) {
+                    close_page(&page, &url).await;
+                    page = self.new_page(&url).await?;
+                }
 
-            // 2) Retry: Load
-            if let Err(e2) = page
-                .goto_builder(url.as_str())
-                .wait_until(playwright::api::DocumentLoadState::Load)
-                .goto()
-                .await
-            {
-                // Ensure we don't leak pages on hard failures.
-                close_page(&page, &url).await;
-                return Err(eyre::eyre!(e2)).wrap_err("goto(Load) failed");
+                // 2) Retry: Load
+                match page
+                    .goto_builder(url.as_str())
+                    .wait_until(playwright::api::DocumentLoadState::Load)
+                    .goto()
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(e2) => {
+                        // Ensure we don't leak pages on hard failures.
+                        close_page(&page, &url).await;
+                        return Err(eyre::eyre!(e2))
+                            .wrap_err("goto(Load) failed");
+                    }
+                }
             }
-        }
+        };
 
-        // Give client-side apps a moment to paint. Non-fatal if it fails.
-        if let Err(e) = page
-            .eval::<bool>(
-                "() => new Promise(r => setTimeout(() => r(true), 250))",
-            )
-            .await
+        // Error pages are still pages as far as the browser's concerned.
+        if let Some(response) = &response
+            && let Err(e) = check_status(response).await
         {
-            debug!(
-                "post-load settle eval failed for {}: {:?} (continuing)",
-                url_brief(&url),
-                e
-            );
+            close_page(&page, &url).await;
+            return Err(e.into());
         }
 
+        // Give client-side apps a chance to load their content.
+        self.settle.for_url(&url).wait(&page).await;
+
         // Extract HTML. If the page got invalidated between navigation and content(),
         // retry once on a fresh page with a simpler wait condition.
         let html = match page.content().await {
@@ -148,14 +590,22 @@ impl HtmlFetcher {
                         url_brief(&url)
                     );
                     close_page(&page, &url).await;
-                    page = self.new_page().await?;
+                    page = self.new_page(&url).await?;
 
-                    page.goto_builder(url.as_str())
+                    response = page
+                        .goto_builder(url.as_str())
                         .wait_until(playwright::api::DocumentLoadState::DomContentLoaded)
                         .goto()
                         .await
                         .wrap_err("retry goto(DomContentLoaded) failed")?;
 
+                    if let Some(response) = &response
+                        && let Err(e) = check_status(response).await
+                    {
+                        close_page(&page, &url).await;
+                        return Err(e.into());
+                    }
+
                     page.content()
                         .await
                         .wrap_err("retry page.content failed")?
@@ -166,104 +616,443 @@ impl HtmlFetcher {
             }
         };
 
+        let (final_url, redirects) = redirects(&url, response.as_ref(), &page);
+        if !redirects.is_empty() {
+            debug!(
+                "{} redirected to {}",
+                url_brief(&url),
+                url_brief(&final_url)
+            );
+        }
+
+        let transferred = match page.eval::<f64>(TRANSFER_SIZE_JS).await {
+            Ok(bytes) => bytes as u64,
+            Err(e) => {
+                debug!(
+                    "transfer size eval failed for {}: {:?}",
+                    url_brief(&url),
+                    e
+                );
+                0
+            }
+        };
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.transferred += transferred;
+            if !self.blocking.is_disabled() {
+                stats.blocked += self.blocking.blocked_in(&final_url, &html);
+            }
+        }
+
         debug!(
-            "fetch ok: {} bytes={} elapsed_ms={}",
+            "fetch ok: {} bytes={} transferred={} elapsed_ms={}",
             url_brief(&url),
             html.len(),
+            transferred,
             started.elapsed().as_millis()
         );
 
         close_page(&page, &url).await;
 
-        Ok(html)
+        Ok(FetchedPage {
+            url: final_url,
+            redirects,
+            html,
+        })
     }
 }
 
-/// Fetch all HTML in parallel, returning a Vec of (Url, Result<html>).
+/// Browser session that fetches batches of pages in parallel
 ///
-/// This runs the entire Playwright pipeline on a single Tokio thread (via `LocalSet`) to avoid
-/// "Object not found" failures that can happen if Playwright objects are used across threads.
-#[tracing::instrument(level = "debug", name = "fetch_all_html", skip_all, fields(concurrency = concurrency))]
-pub async fn fetch_all_html<I>(
-    urls: I,
-    concurrency: usize,
-) -> eyre::Result<Vec<(Url, eyre::Result<String>)>>
-where
-    I: IntoIterator<Item = Url>,
-{
-    let urls: Vec<Url> = urls.into_iter().collect();
+/// Launching Chromium is slow, so callers with several batches to fetch (such
+/// as a crawl, level by level) should fetch them all through one of these.
+/// Playwright objects must stay on a single Tokio thread to avoid "Object not
+/// found" failures, so this must be created and used within a `LocalSet`.
+pub struct BatchFetcher {
+    fetcher: HtmlFetcher,
+    options: FetchOptions,
+}
 
-    let local = LocalSet::new();
-    local
-        .run_until(async move {
-            let fetcher = Arc::new(HtmlFetcher::new().await?);
+impl BatchFetcher {
+    pub async fn new(options: &FetchOptions) -> eyre::Result<Self> {
+        Ok(Self {
+            fetcher: HtmlFetcher::new(options).await?,
+            options: options.clone(),
+        })
+    }
 
-            let total = urls.len();
-            debug!("bulk fetch start: total_urls={total} concurrency={concurrency}");
+    /// Fetch all HTML in parallel, returning a Vec of (requested Url,
+    /// Result<page>)
+    ///
+    /// URLs disallowed by `robots.txt` (when [`FetchOptions::robots`] is set)
+    /// are never requested and fail with [`FetchError::RobotsDisallowed`].
+    /// `file://` URLs are read directly from disk.
+    #[tracing::instrument(level = "debug", name = "fetch_all", skip_all, fields(concurrency = self.options.concurrency))]
+    pub async fn fetch_all<I>(
+        &self,
+        urls: I,
+    ) -> Vec<(Url, eyre::Result<FetchedPage>)>
+    where
+        I: IntoIterator<Item = Url>,
+    {
+        let urls: Vec<Url> = urls.into_iter().collect();
+        let concurrency = self.options.concurrency;
+
+        let total = urls.len();
+        debug!(
+            "bulk fetch start: total_urls={total} concurrency={concurrency}"
+        );
 
-            let started = Instant::now();
-            let mut ok = 0usize;
-            let mut err = 0usize;
-            let mut timeout_err = 0usize;
-            let mut other_err = 0usize;
-            let mut done = 0usize;
+        let started = Instant::now();
+        let mut ok = 0usize;
+        let mut err = 0usize;
+        let mut timeout_err = 0usize;
+        let mut robots_err = 0usize;
+        let mut status_err = 0usize;
+        let mut other_err = 0usize;
+        let mut done = 0usize;
 
-            let per_url_timeout = Duration::from_secs(45);
+        let stream = stream::iter(urls.into_iter().map(|url| async move {
+            (url.clone(), self.fetch_one(url).await)
+        }))
+        .buffer_unordered(concurrency);
 
-            let stream = stream::iter(urls.into_iter().map(|url| {
-                let fetcher = Arc::clone(&fetcher);
-                async move {
-                    let u = url.clone();
-                    let brief = url_brief(&u);
+        let mut out: Vec<(Url, eyre::Result<FetchedPage>)> =
+            Vec::with_capacity(total);
 
-                    let res = match timeout(per_url_timeout, fetcher.fetch_html(url.clone())).await
-                    {
-                        Ok(r) => r,
-                        Err(_) => {
-                            warn!("timeout fetching {}", brief);
-                            Err(eyre::eyre!("timeout after {:?}", per_url_timeout))
-                        }
-                    };
-
-                    (u, res)
-                }
-            }))
-            .buffer_unordered(concurrency);
-
-            let mut out: Vec<(Url, eyre::Result<String>)> = Vec::with_capacity(total);
-
-            tokio::pin!(stream);
-            while let Some((url, res)) = stream.next().await {
-                done += 1;
-                match &res {
-                    Ok(_) => ok += 1,
-                    Err(e) => {
-                        err += 1;
-                        if e.to_string().contains("timeout after") {
-                            timeout_err += 1;
-                        } else {
-                            other_err += 1;
-                        }
-                        debug!("fetch failed: {} err={:?}", url_brief(&url), e);
+        tokio::pin!(stream);
+        while let Some((url, res)) = stream.next().await {
+            done += 1;
+            match &res {
+                Ok(_) => ok += 1,
+                Err(e) => {
+                    err += 1;
+                    match e.downcast_ref::<FetchError>() {
+                        Some(FetchError::Timeout(_)) => timeout_err += 1,
+                        Some(FetchError::RobotsDisallowed) => robots_err += 1,
+                        Some(FetchError::Status { .. }) => status_err += 1,
+                        None => other_err += 1,
                     }
+                    debug!("fetch failed: {} err={:?}", url_brief(&url), e);
+                }
+            }
+
+            if done.is_multiple_of(100) || done == total {
+                debug!(
+                    "bulk fetch progress: done={done}/{total} ok={ok} err={err} timeout_err={timeout_err} robots_err={robots_err} status_err={status_err} other_err={other_err} elapsed_s={}",
+                    started.elapsed().as_secs()
+                );
+            }
+
+            out.push((url, res));
+        }
+
+        debug!(
+            "bulk fetch complete: total={total} ok={ok} err={err} timeout_err={timeout_err} robots_err={robots_err} status_err={status_err} other_err={other_err} elapsed_s={}",
+            started.elapsed().as_secs()
+        );
+
+        out
+    }
+
+    /// Fetch a single URL, honouring `robots.txt` and retrying as configured
+    async fn fetch_one(&self, url: Url) -> eyre::Result<FetchedPage> {
+        let brief = url_brief(&url);
+        let per_url_timeout = self.options.per_url_timeout;
+        let retries = self.options.retries;
+
+        if url.scheme() == "file" {
+            return read_local_file(&url).await;
+        }
+
+        if let Some(robots) = &self.options.robots {
+            if !robots.is_allowed(&url).await {
+                /* a site that can't be reached isn't one we were refused */
+                if let Some(reason) =
+                    robots.get(&url).await.unreachable_reason()
+                {
+                    return Err(eyre::eyre!("{reason}")
+                        .wrap_err("robots.txt couldn't be retrieved"));
+                }
+                debug!("robots.txt disallows {}", brief);
+                return Err(FetchError::RobotsDisallowed.into());
+            }
+            robots.wait_turn(&url).await;
+        }
+
+        let mut attempt = 0;
+        loop {
+            let res = match timeout(
+                per_url_timeout,
+                self.fetcher.fetch_html(url.clone()),
+            )
+            .await
+            {
+                Ok(r) => r,
+                Err(_) => {
+                    warn!("timeout fetching {}", brief);
+                    Err(FetchError::Timeout(per_url_timeout).into())
                 }
+            };
 
-                if done.is_multiple_of(100) || done == total {
+            let delay = match &res {
+                Err(e) if attempt < retries => e
+                    .downcast_ref::<FetchError>()
+                    .and_then(|e| e.retry_delay(attempt)),
+                _ => None,
+            };
+
+            match delay {
+                Some(delay) => {
                     debug!(
-                        "bulk fetch progress: done={done}/{total} ok={ok} err={err} timeout_err={timeout_err} other_err={other_err} elapsed_s={}",
-                        started.elapsed().as_secs()
+                        "retrying {} in {:?} (attempt {}/{})",
+                        brief,
+                        delay,
+                        attempt + 1,
+                        retries
                     );
+                    sleep(delay).await;
+                    /* a retry is still a request as far as Crawl-delay goes */
+                    if let Some(robots) = &self.options.robots {
+                        robots.wait_turn(&url).await;
+                    }
+                    attempt += 1;
                 }
-
-                out.push((url, res));
+                None => break res,
             }
+        }
+    }
 
-            debug!(
-                "bulk fetch complete: total={total} ok={ok} err={err} timeout_err={timeout_err} other_err={other_err} elapsed_s={}",
-                started.elapsed().as_secs()
-            );
+    /// Log how much was transferred and blocked over the fetcher's lifetime
+    pub fn log_stats(&self) {
+        let stats = self.fetcher.stats();
+        info!(
+            "Transferred {} KiB; blocked {} subresources (an estimated {} KiB saved, going by typical sizes)",
+            stats.transferred / 1024,
+            stats.blocked.total(),
+            stats.blocked.estimated_bytes() / 1024
+        );
+    }
+}
 
+/// Fetch all HTML in parallel with a fresh [`BatchFetcher`], returning a Vec
+/// of (requested Url, Result<page>).
+///
+/// This runs the entire Playwright pipeline on a single Tokio thread (via
+/// `LocalSet`).
+pub async fn fetch_all_html<I>(
+    urls: I,
+    options: &FetchOptions,
+) -> eyre::Result<Vec<(Url, eyre::Result<FetchedPage>)>>
+where
+    I: IntoIterator<Item = Url>,
+{
+    LocalSet::new()
+        .run_until(async {
+            let fetcher = BatchFetcher::new(options).await?;
+            let out = fetcher.fetch_all(urls).await;
+            fetcher.log_stats();
             Ok(out)
         })
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16, retry_after: Option<Duration>) -> FetchError {
+        FetchError::Status {
+            status,
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(status(503, None).retry_delay(0), Some(BACKOFF_BASE));
+        assert_eq!(status(503, None).retry_delay(3), Some(BACKOFF_BASE * 8));
+        assert_eq!(
+            status(429, Some(Duration::from_secs(7))).retry_delay(0),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            status(429, Some(MAX_RETRY_WAIT * 2)).retry_delay(0),
+            Some(MAX_RETRY_WAIT)
+        );
+        assert_eq!(status(503, None).retry_delay(30), Some(MAX_RETRY_WAIT));
+        assert_eq!(status(404, None).retry_delay(0), None);
+        assert_eq!(FetchError::RobotsDisallowed.retry_delay(0), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_local_file() {
+        let dir = std::env::temp_dir()
+            .join(format!("slurpsearch-local-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let notes = dir.join("notes.txt");
+        std::fs::write(&notes, "a <b> & c").unwrap();
+        let page = dir.join("page.html");
+        std::fs::write(&page, "<p>hi</p>").unwrap();
+
+        let url = Url::from_file_path(&notes).unwrap();
+        let read = read_local_file(&url).await.unwrap();
+        assert_eq!(read.url, url);
+        assert!(read.html.contains("<pre>a &lt;b&gt; &amp; c</pre>"));
+
+        let url = Url::from_file_path(&page).unwrap();
+        assert_eq!(read_local_file(&url).await.unwrap().html, "<p>hi</p>");
+
+        let missing = Url::from_file_path(dir.join("missing.html")).unwrap();
+        assert!(read_local_file(&missing).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_canonical_url() {
+        let page = |html: &str| FetchedPage {
+            url: Url::parse("https://example.com/a?ref=home").unwrap(),
+            redirects: vec![],
+            html: html.to_string(),
+        };
+
+        assert_eq!(
+            page(r#"<link rel="canonical" href="/a#top">"#)
+                .canonical_url()
+                .as_str(),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            page(r#"<link rel="canonical" href="https://other.example/">"#)
+                .canonical_url()
+                .as_str(),
+            "https://example.com/a?ref=home"
+        );
+        assert_eq!(
+            page("<p>no link</p>").canonical_url().as_str(),
+            "https://example.com/a?ref=home"
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let later = httpdate::fmt_http_date(
+            SystemTime::now() + Duration::from_secs(3600),
+        );
+        assert!(
+            parse_retry_after(&later)
+                .is_some_and(|d| d > Duration::from_secs(3500))
+        );
+    }
+
+    /// Serve `respond(path)` (status line and body) on a local port,
+    /// recording each request's line and headers
+    fn stand_in(
+        respond: impl Fn(&str) -> (String, String) + Send + 'static,
+    ) -> (u16, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                while reader.read_line(&mut request).is_ok_and(|n| n > 2) {}
+                let path = request.split(' ').nth(1).unwrap_or_default();
+
+                let (status, body) = respond(path);
+                recorded.lock().unwrap().push(request.to_ascii_lowercase());
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        (port, requests)
+    }
+
+    #[tokio::test]
+    async fn test_host_headers_stay_on_host() {
+        let (other, other_requests) = stand_in(|_| {
+            ("200 OK".to_string(), "<p>Elsewhere</p>".to_string())
+        });
+        let (port, requests) = stand_in(move |path| match path {
+            "/moved" => (
+                format!(
+                    "302 Found\r\nLocation: http://localhost:{other}/landing"
+                ),
+                String::new(),
+            ),
+            _ => (
+                "200 OK".to_string(),
+                format!(
+                    r#"<img src="http://localhost:{other}/pixel.png"><p>Home</p>"#
+                ),
+            ),
+        });
+
+        let options = FetchOptions {
+            concurrency: 1,
+            robots: None,
+            per_url_timeout: DEFAULT_TIMEOUT,
+            retries: 0,
+            blocking: BlockConfig::default(),
+            settle: SettleConfig::default(),
+            session: SessionConfig {
+                headers: vec![
+                    "X-Requested-By: slurpsearch".parse().unwrap(),
+                    "127.0.0.1=Authorization: Bearer secret".parse().unwrap(),
+                ],
+                ..Default::default()
+            },
+            proxy: ProxyConfig::default(),
+            direct: true,
+        };
+        let fetcher = DirectFetcher::new(&options).unwrap();
+        let base = format!("http://127.0.0.1:{port}");
+
+        let home = Url::parse(&format!("{base}/")).unwrap();
+        assert!(options.session.has_host_headers(&home));
+        let page = fetcher.fetch_html(&home).await.unwrap();
+        assert!(page.html.contains("Home"));
+
+        let moved = Url::parse(&format!("{base}/moved")).unwrap();
+        let page = fetcher.fetch_html(&moved).await.unwrap();
+        assert_eq!(
+            page.url.as_str(),
+            format!("http://localhost:{other}/landing")
+        );
+        assert_eq!(page.redirects, vec![moved]);
+        assert!(page.html.contains("Elsewhere"));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(
+            requests
+                .iter()
+                .all(|r| r.contains("authorization: bearer secret"))
+        );
+
+        /* the page's image isn't loaded, and the redirect target only gets
+        headers meant for every host */
+        let other_requests = other_requests.lock().unwrap();
+        assert_eq!(other_requests.len(), 1);
+        assert!(other_requests[0].starts_with("get /landing "));
+        assert!(other_requests[0].contains("x-requested-by: slurpsearch"));
+        assert!(!other_requests[0].contains("authorization"));
+        assert!(!other_requests[0].contains("secret"));
+    }
+}
@@ -0,0 +1,121 @@
+use regex::Regex;
+use url::Url;
+
+use crate::sites::HostPattern;
+
+/// Narrows the haystack down to the URLs we actually want to search
+#[derive(Clone, Debug, Default)]
+pub struct UrlFilter {
+    /// If nonempty, only keep URLs on one of these domains (or subdomains)
+    pub include_domains: Vec<String>,
+    /// Drop URLs on any of these domains (or subdomains)
+    pub exclude_domains: Vec<String>,
+    /// If nonempty, only keep URLs matching at least one of these
+    pub url_regexes: Vec<Regex>,
+}
+
+/// Number of URLs dropped by each part of a [`UrlFilter`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterCounts {
+    pub not_included: usize,
+    pub excluded: usize,
+    pub unmatched: usize,
+}
+
+impl FilterCounts {
+    pub fn total(&self) -> usize {
+        self.not_included + self.excluded + self.unmatched
+    }
+}
+
+fn on_domain(domains: &[String], host: &str) -> bool {
+    domains.iter().any(|domain| {
+        let domain = domain.trim_start_matches("*.");
+        HostPattern::new(&format!("*.{domain}")).matches(host)
+    })
+}
+
+impl UrlFilter {
+    /// Keep the URLs that pass every filter
+    pub fn apply<I>(&self, urls: I) -> (Vec<Url>, FilterCounts)
+    where
+        I: IntoIterator<Item = Url>,
+    {
+        let mut counts = FilterCounts::default();
+
+        let kept = urls
+            .into_iter()
+            .filter(|url| {
+                let host = url.host_str().unwrap_or_default();
+
+                if !self.include_domains.is_empty()
+                    && !on_domain(&self.include_domains, host)
+                {
+                    counts.not_included += 1;
+                    return false;
+                }
+
+                if on_domain(&self.exclude_domains, host) {
+                    counts.excluded += 1;
+                    return false;
+                }
+
+                if !self.url_regexes.is_empty()
+                    && !self
+                        .url_regexes
+                        .iter()
+                        .any(|re| re.is_match(url.as_str()))
+                {
+                    counts.unmatched += 1;
+                    return false;
+                }
+
+                true
+            })
+            .collect();
+
+        (kept, counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let urls = [
+            "https://github.com/a/b",
+            "https://gist.github.com/c",
+            "https://www.youtube.com/watch?v=1",
+            "https://example.com/post",
+            "https://notgithub.com/",
+        ]
+        .map(|s| Url::parse(s).unwrap());
+
+        let only_github = UrlFilter {
+            include_domains: vec!["github.com".to_string()],
+            ..Default::default()
+        };
+        let (kept, counts) = only_github.apply(urls.clone());
+        assert_eq!(kept, urls[..2]);
+        assert_eq!(counts.not_included, 3);
+
+        let filter = UrlFilter {
+            exclude_domains: vec!["youtube.com".to_string()],
+            url_regexes: vec![Regex::new("/(post|a/)").unwrap()],
+            ..Default::default()
+        };
+        let (kept, counts) = filter.apply(urls.clone());
+        assert_eq!(kept, vec![urls[0].clone(), urls[3].clone()]);
+        assert_eq!(
+            counts,
+            FilterCounts {
+                not_included: 0,
+                excluded: 1,
+                unmatched: 2,
+            }
+        );
+        assert_eq!(counts.total(), 3);
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use eyre::WrapErr;
+use rig::{OneOrMany, embeddings::Embedding};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::info;
+use url::Url;
+
+use crate::{cache::sha256_hex, dates::Timestamp, rag::WebDoc};
+
+/// Default number of days before an indexed page is fetched again
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+/// Embeddings and the documents they came from, persisted between runs so
+/// that only new or changed content needs fetching and embedding
+///
+/// Pages are keyed by haystack URL and remember a hash of the HTML they were
+/// extracted from. Embeddings are keyed by a hash of the text embedded, so
+/// a paragraph that survives a page edit (or appears on several pages) is
+/// only ever embedded once.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Index {
+    /// Model that produced the embeddings
+    pub model: String,
+    pub pages: BTreeMap<Url, IndexedPage>,
+    /// Embedding vector of each distinct text, by [`text_hash`]
+    pub embeddings: HashMap<String, Vector>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct IndexedPage {
+    /// When the page was last fetched
+    pub fetched: Timestamp,
+    /// Hash of the HTML the documents were extracted from
+    pub content_hash: String,
+    pub docs: Vec<WebDoc>,
+}
+
+/// Embedding vector, stored as base64-encoded little-endian `f32`s since
+/// JSON arrays of thousands of floats per paragraph add up quickly
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Vector(pub Vec<f64>);
+
+impl Serialize for Vector {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = self
+            .0
+            .iter()
+            .flat_map(|x| (*x as f32).to_le_bytes())
+            .collect();
+        s.serialize_str(&STANDARD.encode(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Vector {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bytes = STANDARD
+            .decode(String::deserialize(d)?)
+            .map_err(serde::de::Error::custom)?;
+        Ok(Self(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                .collect(),
+        ))
+    }
+}
+
+/// Key an embedding is stored under
+pub fn text_hash(text: &str) -> String {
+    sha256_hex(text.as_bytes())
+}
+
+/// What changed in the course of an update
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    pub added: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub embedded: usize,
+    pub pruned: usize,
+}
+
+impl Index {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Where the index lives by default, i.e.
+    /// `$XDG_CACHE_HOME/slurpsearch/index.json` (or under `~/.cache`)
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })?;
+
+        Some(dir.join(env!("CARGO_PKG_NAME")).join("index.json"))
+    }
+
+    /// Read the index at `path`, starting afresh if there isn't one yet
+    ///
+    /// Embeddings from a different model are discarded, as they can't be
+    /// compared with new ones.
+    pub fn load(path: &Path, model: &str) -> eyre::Result<Self> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Self::new(model));
+            }
+            Err(e) => {
+                return Err(e).wrap_err_with(|| {
+                    format!("failed to read {}", path.display())
+                });
+            }
+        };
+
+        let mut index: Self = serde_json::from_slice(&contents)
+            .wrap_err_with(|| format!("corrupt index {}", path.display()))?;
+        if index.model != model {
+            info!(
+                "Discarding embeddings from {} in favour of {model}",
+                index.model
+            );
+            index.embeddings.clear();
+            index.model = model.to_string();
+        }
+
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).wrap_err_with(|| {
+                format!("failed to create {}", dir.display())
+            })?;
+        }
+
+        /* write then rename, so a crash can't leave half an index */
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .wrap_err_with(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path)
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Forget pages that are no longer in the haystack, returning how many
+    pub fn retain_urls(&mut self, urls: &HashSet<Url>) -> usize {
+        let before = self.pages.len();
+        self.pages.retain(|url, _| urls.contains(url));
+        before - self.pages.len()
+    }
+
+    /// Whether `url` is new or was last fetched longer than `max_age` ago
+    pub fn needs_fetch(
+        &self,
+        url: &Url,
+        max_age: Duration,
+        now: Timestamp,
+    ) -> bool {
+        self.pages.get(url).is_none_or(|page| {
+            (now - page.fetched).to_std().is_ok_and(|age| age > max_age)
+        })
+    }
+
+    /// Record a fresh fetch of `url`, extracting documents with `extract`
+    /// only if the HTML differs from last time
+    pub fn update_page<F>(
+        &mut self,
+        url: Url,
+        html: &str,
+        fetched: Timestamp,
+        extract: F,
+        stats: &mut IndexStats,
+    ) where
+        F: FnOnce() -> Vec<WebDoc>,
+    {
+        let content_hash = sha256_hex(html.as_bytes());
+
+        match self.pages.get_mut(&url) {
+            Some(page) if page.content_hash == content_hash => {
+                page.fetched = fetched;
+                stats.unchanged += 1;
+            }
+            existing => {
+                match existing {
+                    Some(_) => stats.changed += 1,
+                    None => stats.added += 1,
+                }
+                self.pages.insert(
+                    url,
+                    IndexedPage {
+                        fetched,
+                        content_hash,
+                        docs: extract(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Stamp every document from `url` with when it was bookmarked, which
+    /// may have changed without the page itself changing
+    pub fn set_bookmarked(&mut self, bookmarked: &HashMap<Url, Timestamp>) {
+        for (url, page) in &mut self.pages {
+            for doc in &mut page.docs {
+                doc.bookmarked = bookmarked.get(url).copied();
+            }
+        }
+    }
+
+    /// One document for each distinct text that hasn't been embedded yet
+    pub fn unembedded(&self) -> Vec<WebDoc> {
+        let mut seen = HashSet::new();
+        self.pages
+            .values()
+            .flat_map(|page| &page.docs)
+            .filter(|doc| {
+                let hash = text_hash(&doc.text);
+                !self.embeddings.contains_key(&hash) && seen.insert(hash)
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn add_embeddings<I>(&mut self, embeddings: I)
+    where
+        I: IntoIterator<Item = (WebDoc, OneOrMany<Embedding>)>,
+    {
+        for (doc, embedding) in embeddings {
+            self.embeddings
+                .insert(text_hash(&doc.text), Vector(embedding.first().vec));
+        }
+    }
+
+    /// Drop embeddings no document refers to any more, returning how many
+    pub fn prune_embeddings(&mut self) -> usize {
+        let used: HashSet<String> = self
+            .pages
+            .values()
+            .flat_map(|page| &page.docs)
+            .map(|doc| text_hash(&doc.text))
+            .collect();
+        let before = self.embeddings.len();
+        self.embeddings.retain(|hash, _| used.contains(hash));
+        before - self.embeddings.len()
+    }
+
+    /// Embedded documents that satisfy `keep`, ready for a vector store
+    ///
+    /// `keep` is given the URL each page is indexed under (the one in the
+    /// haystack) as well as the document, whose own URL is wherever the
+    /// page ended up after any redirects.
+    pub fn embedded_docs<F>(
+        &self,
+        keep: F,
+    ) -> Vec<(WebDoc, OneOrMany<Embedding>)>
+    where
+        F: Fn(&Url, &WebDoc) -> bool,
+    {
+        self.pages
+            .iter()
+            .flat_map(|(url, page)| page.docs.iter().map(move |doc| (url, doc)))
+            .filter(|(url, doc)| keep(url, doc))
+            .map(|(_, doc)| doc)
+            .filter_map(|doc| {
+                let vector = self.embeddings.get(&text_hash(&doc.text))?;
+                let embedding = Embedding {
+                    document: doc.text.clone(),
+                    vec: vector.0.clone(),
+                };
+                Some((doc.clone(), OneOrMany::one(embedding)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dates::parse_timestamp, search::TextPosition};
+
+    fn doc(url: &Url, text: &str) -> WebDoc {
+        WebDoc {
+            url: url.clone(),
+            text: text.to_string(),
+            position: TextPosition { line: 1, column: 1 },
+            anchor: None,
+            kind: Default::default(),
+            seed: None,
+            bookmarked: None,
+            published: None,
+            archived: None,
+        }
+    }
+
+    fn embedded(docs: Vec<WebDoc>) -> Vec<(WebDoc, OneOrMany<Embedding>)> {
+        docs.into_iter()
+            .map(|doc| {
+                let embedding = Embedding {
+                    document: doc.text.clone(),
+                    vec: vec![doc.text.len() as f64, 0.5],
+                };
+                (doc, OneOrMany::one(embedding))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let a = Url::parse("https://x.com/a").unwrap();
+        let b = Url::parse("https://x.com/b").unwrap();
+        let then = parse_timestamp("2025-12-01").unwrap();
+        let now = parse_timestamp("2025-12-03").unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        let mut index = Index::new("model");
+        let mut stats = IndexStats::default();
+        index.update_page(
+            a.clone(),
+            "<p>1</p>",
+            then,
+            || vec![doc(&a, "one"), doc(&a, "shared")],
+            &mut stats,
+        );
+        index.update_page(
+            b.clone(),
+            "<p>2</p>",
+            then,
+            || vec![doc(&b, "shared")],
+            &mut stats,
+        );
+        assert_eq!(stats.added, 2);
+
+        /* the shared paragraph is only embedded once */
+        let pending = index.unembedded();
+        assert_eq!(pending.len(), 2);
+        index.add_embeddings(embedded(pending));
+        assert!(index.unembedded().is_empty());
+        assert_eq!(index.embedded_docs(|_, _| true).len(), 3);
+
+        assert!(!index.needs_fetch(&a, 7 * day, now));
+        assert!(index.needs_fetch(&a, day, now));
+        assert!(index.needs_fetch(
+            &Url::parse("https://x.com/new").unwrap(),
+            7 * day,
+            now
+        ));
+
+        /* unchanged HTML isn't even re-extracted */
+        index.update_page(
+            a.clone(),
+            "<p>1</p>",
+            now,
+            || unreachable!(),
+            &mut stats,
+        );
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(index.pages[&a].fetched, now);
+
+        index.update_page(
+            a.clone(),
+            "<p>1!</p>",
+            now,
+            || vec![doc(&a, "one!"), doc(&a, "shared")],
+            &mut stats,
+        );
+        assert_eq!(stats.changed, 1);
+        assert_eq!(index.unembedded(), vec![doc(&a, "one!")]);
+
+        assert_eq!(index.retain_urls(&HashSet::from([a.clone()])), 1);
+        assert_eq!(index.prune_embeddings(), 1);
+        assert_eq!(index.embeddings.len(), 1);
+    }
+
+    #[test]
+    fn test_redirected_page() {
+        let requested = Url::parse("https://x.com/old").unwrap();
+        let landed = Url::parse("https://x.com/new").unwrap();
+        let now = parse_timestamp("2025-12-03").unwrap();
+
+        let mut index = Index::new("model");
+        index.update_page(
+            requested.clone(),
+            "<p>moved</p>",
+            now,
+            || vec![doc(&landed, "moved")],
+            &mut IndexStats::default(),
+        );
+        index.add_embeddings(embedded(index.unembedded()));
+
+        /* found under the URL requested, even though it ended up elsewhere */
+        let found = index.embedded_docs(|url, _| url == &requested);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.url, landed);
+        assert!(index.embedded_docs(|url, _| url == &landed).is_empty());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = env::temp_dir()
+            .join(format!("slurpsearch-index-{}", std::process::id()))
+            .join("index.json");
+        let url = Url::parse("https://x.com/a").unwrap();
+        let fetched = parse_timestamp("2025-12-01").unwrap();
+
+        let mut index = Index::new("model");
+        index.update_page(
+            url.clone(),
+            "",
+            fetched,
+            || vec![doc(&url, "text")],
+            &mut IndexStats::default(),
+        );
+        index.add_embeddings(embedded(index.unembedded()));
+        index.save(&path).unwrap();
+
+        let loaded = Index::load(&path, "model").unwrap();
+        assert_eq!(loaded.pages, index.pages);
+        assert_eq!(loaded.embeddings, index.embeddings);
+
+        let other = Index::load(&path, "other-model").unwrap();
+        assert!(other.embeddings.is_empty());
+        assert_eq!(other.pages, index.pages);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -1,20 +1,373 @@
 use crate::{
-    cli::Opts,
-    extract::extract_text,
-    fetch::*,
-    rag::{RagStore, WebDoc},
+    archive::Archive,
+    block::BlockConfig,
+    cache::{CachedPage, PageCache},
+    canonical::CanonicalConfig,
+    check::check,
+    chunk::{ChunkConfig, chunk_docs},
+    cli::{CheckOpts, Command, CommonOpts, DiffOpts, IndexArgs, Opts},
+    config::Config,
+    crawl::{CrawlConfig, CrawledPage, crawl},
+    dates::{DateRange, Timestamp, published_date},
+    diff::PageDiff,
+    discover::{Discovered, discover},
+    embed::{EmbedConfig, EmbedEstimate},
+    fetch::{FetchError, FetchOptions, FetchedPage, fetch_all_html},
+    filter::UrlFilter,
+    http::ProxyConfig,
+    index::{Index, IndexStats},
+    rag::{EMBEDDING_MODEL, RagStore, WebDoc, embed_documents},
+    robots::RobotsCache,
     search::*,
+    session::{HostHeader, SessionConfig},
+    settle::SettleConfig,
+    sites::ExtractorRegistry,
+    warc::{WarcPage, WarcWriter},
 };
+use chrono::Utc;
 use clap::Parser;
-use std::fs;
-use tracing::{error, info};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
+pub mod archive;
+pub mod block;
+pub mod cache;
+pub mod canonical;
+pub mod check;
+pub mod chunk;
 pub mod cli;
+pub mod config;
+pub mod crawl;
+pub mod dates;
+pub mod diff;
+pub mod discover;
+pub mod embed;
 pub mod extract;
 pub mod fetch;
+pub mod filter;
+pub mod http;
+pub mod index;
+pub mod provider;
 pub mod rag;
+pub mod robots;
 pub mod search;
+pub mod session;
+pub mod settle;
+pub mod sites;
+pub mod tokenize;
+pub mod warc;
+
+/// URLs from the haystack that survived canonicalization and filtering
+struct Haystack {
+    urls: Vec<Url>,
+    /// Every (canonical) URL in the haystack, before any filtering
+    all_urls: HashSet<Url>,
+    /// When each (canonical) URL was bookmarked, if known
+    bookmarked: HashMap<Url, Timestamp>,
+    canonical: CanonicalConfig,
+}
+
+/// A page that made it through fetching and filtering, ready for extraction
+struct Retrieved {
+    url: Url,
+    /// Haystack URL the page was reached from, if not the page itself
+    seed: Option<Url>,
+    html: String,
+    bookmarked: Option<Timestamp>,
+    published: Option<Timestamp>,
+    /// Snapshot the HTML came from, if the page itself was unavailable
+    archived: Option<Url>,
+}
+
+/// Which URLs to consider, according to the command line and config file
+fn url_filter(opts: &CommonOpts, config: &Config) -> UrlFilter {
+    /* explicitly included domains trump the configured exclusions */
+    UrlFilter {
+        include_domains: opts.include_domains.clone(),
+        exclude_domains: config
+            .exclude_domains
+            .iter()
+            .filter(|domain| !opts.include_domains.contains(domain))
+            .chain(&opts.exclude_domains)
+            .cloned()
+            .collect(),
+        url_regexes: opts.url_regexes.clone(),
+    }
+}
+
+/// Cache of fetched pages, according to the command line
+fn page_cache(opts: &CommonOpts) -> eyre::Result<PageCache> {
+    opts.cache_dir
+        .clone()
+        .or_else(PageCache::default_dir)
+        .map(PageCache::new)
+        .ok_or_else(|| eyre::eyre!("no cache directory; pass --cache-dir"))
+}
+
+/// Read the haystack at `path` and narrow its URLs down to those we want
+///
+/// WARC files hold pages rather than URLs, so only a plain search (which
+/// reads them with [`warc_pages`]) can use one.
+fn load_haystack(path: &Path, opts: &CommonOpts) -> eyre::Result<Haystack> {
+    if warc::is_warc(path) {
+        eyre::bail!(
+            "{} is a WARC file, which can only be searched directly (without --incremental or a subcommand)",
+            path.display()
+        );
+    }
+    let config = Config::load(opts.config.as_deref())?;
+    let contents = fs::read_to_string(path)?;
+    let bookmarks = extract_bookmarks(&contents);
+    let mut seen = HashSet::new();
+    let urls: Vec<Url> = bookmarks
+        .iter()
+        .map(|b| b.url.clone())
+        .filter(|url| seen.insert(url.clone()))
+        .collect();
+    info!("Extracted {} URLs from {}", urls.len(), path.display());
+
+    let canonical = CanonicalConfig {
+        strip_params: CanonicalConfig::default()
+            .strip_params
+            .into_iter()
+            .filter(|param| !opts.keep_params.contains(param))
+            .chain(opts.strip_params.iter().cloned())
+            .collect(),
+        strip_fragment: !opts.keep_fragments,
+        trailing_slash: opts.trailing_slash,
+    };
+    let (urls, collapsed) = canonical.dedupe(urls);
+    if collapsed > 0 {
+        info!("Collapsed {collapsed} duplicate URLs after canonicalization");
+    }
+    let all_urls = urls.iter().cloned().collect();
+
+    let dates = DateRange {
+        since: opts.since,
+        until: opts.until,
+    };
+    let mut bookmarked: HashMap<Url, Timestamp> = HashMap::new();
+    for bookmark in &bookmarks {
+        let Some(t) = bookmark.bookmarked else {
+            continue;
+        };
+        /* a link saved more than once counts if any of its saves is in range */
+        let url = canonical.canonicalize(bookmark.url.clone());
+        if !bookmarked
+            .get(&url)
+            .is_some_and(|prev| dates.contains(prev))
+        {
+            bookmarked.insert(url, t);
+        }
+    }
+
+    let (urls, filtered) = url_filter(opts, &config).apply(urls);
+    if filtered.total() > 0 {
+        info!(
+            "Filtered out {} URLs ({} not on an included domain, {} on an excluded domain, {} not matching --url-regex); {} remain",
+            filtered.total(),
+            filtered.not_included,
+            filtered.excluded,
+            filtered.unmatched,
+            urls.len()
+        );
+    }
+
+    /* undated links might still have a publication date in range */
+    let (urls, out_of_range): (Vec<Url>, Vec<Url>) = urls
+        .into_iter()
+        .partition(|url| bookmarked.get(url).is_none_or(|t| dates.contains(t)));
+    if !out_of_range.is_empty() {
+        info!(
+            "Skipped {} URLs bookmarked outside the requested date range",
+            out_of_range.len()
+        );
+    }
+
+    Ok(Haystack {
+        urls,
+        all_urls,
+        bookmarked,
+        canonical,
+    })
+}
+
+/// Pages captured in the WARC file at `path`, as if we'd just crawled them,
+/// along with when each was captured
+///
+/// A capture is as close to a bookmark as a WARC file has, so its date
+/// stands in for one when filtering by date.
+fn warc_pages(
+    path: &Path,
+    opts: &CommonOpts,
+) -> eyre::Result<(Vec<CrawledPage>, HashMap<Url, Timestamp>)> {
+    let config = Config::load(opts.config.as_deref())?;
+    /* crawls often capture a page more than once; the first will do */
+    let mut seen = HashSet::new();
+    let pages: Vec<WarcPage> = warc::read_pages(path)?
+        .into_iter()
+        .filter(|page| seen.insert(page.url.clone()))
+        .collect();
+    info!("Read {} pages from {}", pages.len(), path.display());
+
+    let (urls, filtered) = url_filter(opts, &config)
+        .apply(pages.iter().map(|page| page.url.clone()));
+    if filtered.total() > 0 {
+        info!(
+            "Filtered out {} pages; {} remain",
+            filtered.total(),
+            urls.len()
+        );
+    }
+    let kept: HashSet<Url> = urls.into_iter().collect();
+    let pages: Vec<WarcPage> = pages
+        .into_iter()
+        .filter(|page| kept.contains(&page.url))
+        .collect();
+
+    let captured = pages
+        .iter()
+        .filter_map(|page| Some((page.url.clone(), page.date?)))
+        .collect();
+    let pages = pages
+        .into_iter()
+        .map(|page| CrawledPage {
+            url: page.url.clone(),
+            seed: page.url.clone(),
+            depth: 0,
+            page: Ok(FetchedPage {
+                url: page.url,
+                redirects: vec![],
+                html: page.html,
+            }),
+        })
+        .collect();
+
+    Ok((pages, captured))
+}
+
+/// When the page requested as `requested` (and found at `url`) was
+/// bookmarked, if known
+///
+/// Pages found by crawling or discovery weren't bookmarked themselves, so
+/// they take the date of the haystack URL they were reached from, `seed`.
+fn bookmark_date(
+    bookmarked: &HashMap<Url, Timestamp>,
+    requested: &Url,
+    url: &Url,
+    seed: &Url,
+) -> Option<Timestamp> {
+    [requested, url, seed]
+        .into_iter()
+        .find_map(|url| bookmarked.get(url))
+        .copied()
+}
+
+/// Keep freshly fetched pages in the cache and, if asked, a WARC file
+fn save_pages(pages: &[CrawledPage], opts: &Opts) -> eyre::Result<()> {
+    let fetched = Utc::now().fixed_offset();
+    let fetched_pages = pages.iter().filter_map(|crawled| {
+        Some((&crawled.url, crawled.page.as_ref().ok()?))
+    });
+
+    if !opts.no_cache {
+        let cache = page_cache(&opts.common)?;
+        for (url, page) in fetched_pages.clone() {
+            let cached = cache.put(&CachedPage {
+                url: url.clone(),
+                final_url: page.url.clone(),
+                fetched,
+                html: page.html.clone(),
+            });
+            if let Err(e) = cached {
+                warn!("Failed to cache {url}: {e}");
+            }
+        }
+    }
+
+    if let Some(path) = &opts.warc_out {
+        let mut writer = WarcWriter::create(path)?;
+        let mut written = 0usize;
+        for (_, page) in fetched_pages {
+            writer.write_page(&page.url, fetched, &page.html)?;
+            written += 1;
+        }
+        writer.finish()?;
+        info!("Wrote {written} pages to {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// How to embed documents, according to the command line
+fn embed_config(indexing: &IndexArgs) -> EmbedConfig {
+    EmbedConfig {
+        batch_size: indexing.batch_size,
+        concurrency: indexing.embed_concurrency,
+        tokens_per_minute: (indexing.tokens_per_minute > 0)
+            .then_some(indexing.tokens_per_minute),
+        retries: indexing.embed_retries,
+    }
+}
+
+/// How to fetch pages, according to the command line
+fn fetch_options(
+    opts: &CommonOpts,
+    client: &reqwest::Client,
+    proxy: &ProxyConfig,
+) -> eyre::Result<FetchOptions> {
+    let mut session = SessionConfig {
+        headers: opts.headers.clone(),
+        ..Default::default()
+    };
+    for path in &opts.cookies {
+        session.load_cookies(path)?;
+    }
+    for user_agent in &opts.user_agents {
+        let header = HostHeader::parse_with_name("User-Agent", user_agent);
+        match header.hosts {
+            Some(_) => session.headers.push(header),
+            None => session.user_agent = Some(header.value),
+        }
+    }
+
+    /* the proxy resolves hostnames, so Chromium's resolver rules never apply */
+    let block_trackers = !opts.allow_trackers && proxy.server.is_none();
+    if !opts.allow_trackers && !block_trackers {
+        warn!("Ad and tracker domains can't be blocked through a proxy");
+    }
+
+    Ok(FetchOptions {
+        concurrency: 32,
+        robots: (!opts.ignore_robots)
+            .then(|| Arc::new(RobotsCache::new(client.clone()))),
+        per_url_timeout: Duration::from_secs(opts.timeout),
+        retries: opts.retries,
+        blocking: BlockConfig {
+            resources: !opts.load_resources,
+            block_trackers,
+            trackers: BlockConfig::default()
+                .trackers
+                .into_iter()
+                .chain(opts.block_domains.iter().cloned())
+                .collect(),
+        },
+        settle: SettleConfig {
+            default: opts.settle.clone(),
+            hosts: opts.settle_hosts.clone(),
+        },
+        session,
+        proxy: proxy.clone(),
+        direct: opts.direct_for_headers,
+    })
+}
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -23,22 +376,251 @@ async fn main() -> eyre::Result<()> {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "info".into()),
         )
+        .with_writer(std::io::stderr)
         .init();
     let opts = Opts::parse();
-    let contents = fs::read_to_string(&opts.haystack)?;
-    let urls = extract_urls(contents.as_ref());
-    info!(
-        "Extracted {} URLs from {}",
-        urls.len(),
-        opts.haystack.display()
+
+    match opts.command.clone() {
+        Some(Command::Check(check)) => check_links(check).await,
+        Some(Command::Diff(diff)) => diff_pages(diff).await,
+        Some(Command::Index(index)) => {
+            let haystack = load_haystack(&index.haystack, &index.common)?;
+            update_index(&haystack, &index.indexing, &index.common).await?;
+            Ok(())
+        }
+        None => search(opts).await,
+    }
+}
+
+/// Report on the status of every link in the haystack
+async fn check_links(opts: CheckOpts) -> eyre::Result<()> {
+    let haystack = load_haystack(&opts.haystack, &opts.common)?;
+    let proxy = ProxyConfig::from_env_or(
+        opts.common.proxy.clone(),
+        opts.common.no_proxy.clone(),
+    );
+    let client = http::client(&proxy)?;
+    let fetching = fetch_options(&opts.common, &client, &proxy)?;
+
+    info!("Checking {} URLs...", haystack.urls.len());
+    let reports = check(haystack.urls, &fetching).await?;
+    let dead = reports.iter().filter(|r| !r.status.is_alive()).count();
+    info!("{dead} of {} links are dead", reports.len());
+    print!("{}", check::render(&reports, opts.format)?);
+
+    Ok(())
+}
+
+/// Report on how cached pages have changed, updating the cache as we go
+async fn diff_pages(opts: DiffOpts) -> eyre::Result<()> {
+    let config = Config::load(opts.common.config.as_deref())?;
+    let cache = page_cache(&opts.common)?;
+    let cached: HashMap<Url, CachedPage> = cache
+        .entries()?
+        .into_iter()
+        .map(|page| (page.url.clone(), page))
+        .collect();
+    let (urls, _) =
+        url_filter(&opts.common, &config).apply(cached.keys().cloned());
+    if urls.is_empty() {
+        info!("No cached pages in {}", cache.dir().display());
+        return Ok(());
+    }
+
+    let proxy = ProxyConfig::from_env_or(
+        opts.common.proxy.clone(),
+        opts.common.no_proxy.clone(),
+    );
+    let client = http::client(&proxy)?;
+    let fetching = fetch_options(&opts.common, &client, &proxy)?;
+
+    info!("Re-fetching {} cached pages...", urls.len());
+    let extractors = ExtractorRegistry::default();
+    let mut failed = 0usize;
+    let mut diffs = vec![];
+    for (url, page) in fetch_all_html(urls, &fetching).await? {
+        let page = match page {
+            Ok(page) => page,
+            Err(e) => {
+                debug!("couldn't re-fetch {url}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        let previous = &cached[&url];
+
+        diffs.extend(PageDiff::between(
+            &extractors,
+            &page.url,
+            (previous.fetched, &previous.html),
+            &page.html,
+        ));
+        cache.put(&CachedPage {
+            url,
+            final_url: page.url,
+            fetched: Utc::now().fixed_offset(),
+            html: page.html,
+        })?;
+    }
+    if failed > 0 {
+        warn!(
+            "Couldn't re-fetch {failed} pages; kept their previous snapshots"
+        );
+    }
+    diffs.sort_by(|a, b| a.url.cmp(&b.url));
+    info!("{} pages changed", diffs.len());
+    print!("{}", diff::render(&diffs, opts.format)?);
+
+    Ok(())
+}
+
+/// Search the pages in the haystack for the prompt
+async fn search(opts: Opts) -> eyre::Result<()> {
+    /* clap requires both unless there's a subcommand */
+    let (Some(path), Some(prompt)) = (&opts.haystack, &opts.prompt) else {
+        unreachable!("haystack and prompt are required");
+    };
+    /* there's nothing to fetch, so nothing to crawl, discover, recover,
+    index or archive */
+    if warc::is_warc(path)
+        && (opts.depth > 0
+            || opts.discover
+            || opts.archive_fallback
+            || opts.incremental
+            || opts.warc_out.is_some())
+    {
+        eyre::bail!(
+            "--depth, --discover, --archive-fallback, --incremental and --warc-out can't be used with a WARC file"
+        );
+    }
+    if opts.incremental {
+        return search_index(path, prompt, &opts).await;
+    }
+    let dates = DateRange {
+        since: opts.common.since,
+        until: opts.common.until,
+    };
+    let proxy = ProxyConfig::from_env_or(
+        opts.common.proxy.clone(),
+        opts.common.no_proxy.clone(),
     );
+    let client = http::client(&proxy)?;
+
+    let (pages, bookmarked, discovered) = if warc::is_warc(path) {
+        let (pages, captured) = warc_pages(path, &opts.common)?;
+        (pages, captured, Discovered::default())
+    } else {
+        let Haystack {
+            urls,
+            bookmarked,
+            canonical,
+            ..
+        } = load_haystack(path, &opts.common)?;
+
+        let discovered = if opts.discover {
+            info!("Discovering feeds and sitemaps...");
+            discover(&client, urls, 32, opts.max_discovered).await
+        } else {
+            Discovered {
+                urls,
+                ..Default::default()
+            }
+        };
 
-    info!("Retrieving HTML...");
-    let successful: Vec<(Url, String)> = fetch_all_html(urls, 32)
-        .await?
+        info!("Retrieving HTML...");
+        let fetching = fetch_options(&opts.common, &client, &proxy)?;
+        let crawling = CrawlConfig {
+            depth: opts.depth,
+            same_origin: !opts.cross_origin,
+            include: opts.crawl_include.clone(),
+            exclude: opts.crawl_exclude.clone(),
+            max_pages: opts.max_pages,
+            canonical,
+        };
+        let pages =
+            crawl(discovered.urls.clone(), &fetching, &crawling).await?;
+        save_pages(&pages, &opts)?;
+
+        (pages, bookmarked, discovered)
+    };
+    let disallowed = pages
+        .iter()
+        .filter(|page| {
+            page.page.as_ref().is_err_and(|e| {
+                matches!(
+                    e.downcast_ref::<FetchError>(),
+                    Some(FetchError::RobotsDisallowed)
+                )
+            })
+        })
+        .count();
+    if disallowed > 0 {
+        info!("Skipped {disallowed} URLs disallowed by robots.txt");
+    }
+    /* only bookmarks are worth digging up, not links found by crawling */
+    let dead: Vec<(Url, Option<Timestamp>)> = pages
+        .iter()
+        .filter(|page| {
+            page.depth == 0 && archive::is_dead(&page.url, &page.page)
+        })
+        .map(|page| (page.url.clone(), bookmarked.get(&page.url).copied()))
+        .collect();
+    let archived = if opts.archive_fallback && !dead.is_empty() {
+        info!(
+            "Looking up {} unavailable URLs in the archive...",
+            dead.len()
+        );
+        let archive =
+            Archive::new(client.clone(), opts.archive_endpoint.clone());
+        let archived = archive.retrieve_all(dead, 8).await;
+        info!("Recovered {} pages from the archive", archived.len());
+        archived
+    } else {
+        vec![]
+    };
+
+    let mut undated = 0usize;
+    let mut retrieved = |requested: Url,
+                         url: Url,
+                         seed: Url,
+                         html: String,
+                         archived: Option<Url>| {
+        let bookmarked = bookmark_date(&bookmarked, &requested, &url, &seed);
+        let published = published_date(&html);
+        if dates.is_bounded()
+            && !bookmarked.or(published).is_some_and(|t| dates.contains(&t))
+        {
+            undated += 1;
+            return None;
+        }
+
+        Some(Retrieved {
+            seed: (seed != url).then_some(seed),
+            url,
+            html,
+            bookmarked,
+            published,
+            archived,
+        })
+    };
+    let mut successful: Vec<Retrieved> = pages
         .into_iter()
-        .filter_map(|(url, res)| res.ok().map(|html| (url, html)))
+        .filter_map(|page| {
+            let seed = discovered.seed_of(&page.seed).clone();
+            let requested = page.url;
+            let page = page.page.ok()?;
+            retrieved(requested, page.url, seed, page.html, None)
+        })
         .collect();
+    successful.extend(archived.into_iter().filter_map(|(url, page)| {
+        let seed = discovered.seed_of(&url).clone();
+        retrieved(url.clone(), url, seed, page.html, Some(page.snapshot.url))
+    }));
+    if undated > 0 {
+        info!(
+            "Skipped {undated} pages with no bookmark or publication date in the requested range"
+        );
+    }
     info!("Retrieved {} webpages", successful.len());
 
     if successful.is_empty() {
@@ -46,21 +628,51 @@ async fn main() -> eyre::Result<()> {
     }
 
     info!("Extracting text from webpages...");
+    let chunking = ChunkConfig {
+        max_tokens: opts.indexing.chunk_tokens,
+        overlap_tokens: opts.indexing.chunk_overlap,
+    };
+    let extractors = ExtractorRegistry::default();
     let docs: Vec<WebDoc> = successful
         .iter()
-        .filter_map(|(url, html)| extract_text(url.clone(), html).ok())
-        .flatten()
+        .filter_map(|page| {
+            extractors
+                .extract(page.url.clone(), &page.html)
+                .ok()
+                .map(|docs| {
+                    docs.into_iter()
+                        .map(|doc| WebDoc {
+                            seed: page.seed.clone(),
+                            bookmarked: page.bookmarked,
+                            published: page.published,
+                            archived: page.archived.clone(),
+                            ..doc
+                        })
+                        .collect()
+                })
+        })
+        .flat_map(|blocks| chunk_docs(blocks, &chunking))
         .collect();
     info!("Text extraction complete");
 
+    let embedding = embed_config(&opts.indexing);
+    if opts.indexing.dry_run {
+        println!("{}", EmbedEstimate::new(&docs, &embedding));
+        return Ok(());
+    }
+
     info!("Embedding {} documents...", docs.len());
-    let rag = RagStore::try_from_documents(docs)
+    let rag = RagStore::try_from_documents(docs, &proxy, &embedding)
         .await
         .inspect_err(|e| error!("Failed to embed webpages: {e}"))?;
     info!("Embedded documents");
+    print_findings(&rag, prompt).await
+}
+
+async fn print_findings(rag: &RagStore, prompt: &str) -> eyre::Result<()> {
     info!("Commencing search...");
     let findings = rag
-        .search(&opts.prompt, Some(DEFAULT_RELEVANCE_THRESHOLD))
+        .search(prompt)
         .await
         .inspect_err(|e| error!("Failed to prompt model: {e}"))?;
     info!("Found {} findings", findings.len());
@@ -68,3 +680,214 @@ async fn main() -> eyre::Result<()> {
 
     Ok(())
 }
+
+/// Bring the persistent index up to date with the haystack, fetching only
+/// new or stale pages and embedding only text we haven't seen before
+async fn update_index(
+    haystack: &Haystack,
+    indexing: &IndexArgs,
+    opts: &CommonOpts,
+) -> eyre::Result<Index> {
+    let path = indexing
+        .index
+        .clone()
+        .or_else(Index::default_path)
+        .ok_or_else(|| eyre::eyre!("no index location; pass --index"))?;
+    let mut index = Index::load(&path, EMBEDDING_MODEL)?;
+    let mut stats = IndexStats {
+        removed: index.retain_urls(&haystack.all_urls),
+        ..Default::default()
+    };
+
+    let now = Utc::now().fixed_offset();
+    let max_age = Duration::from_secs(indexing.max_age * 24 * 60 * 60);
+    let stale: Vec<Url> = haystack
+        .urls
+        .iter()
+        .filter(|url| index.needs_fetch(url, max_age, now))
+        .cloned()
+        .collect();
+
+    let proxy =
+        ProxyConfig::from_env_or(opts.proxy.clone(), opts.no_proxy.clone());
+    if !stale.is_empty() {
+        info!("Fetching {} new or stale URLs...", stale.len());
+        let client = http::client(&proxy)?;
+        let fetching = fetch_options(opts, &client, &proxy)?;
+        let chunking = ChunkConfig {
+            max_tokens: indexing.chunk_tokens,
+            overlap_tokens: indexing.chunk_overlap,
+        };
+        let extractors = ExtractorRegistry::default();
+
+        let mut failed = 0usize;
+        for (url, page) in fetch_all_html(stale, &fetching).await? {
+            let Ok(page) = page else {
+                failed += 1;
+                continue;
+            };
+            let published = published_date(&page.html);
+            let extract = || {
+                let docs = extractors
+                    .extract(page.url.clone(), &page.html)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|doc| WebDoc { published, ..doc })
+                    .collect();
+                chunk_docs(docs, &chunking)
+            };
+            index.update_page(url, &page.html, now, extract, &mut stats);
+        }
+        if failed > 0 {
+            warn!("Couldn't fetch {failed} URLs; kept any earlier versions");
+        }
+    }
+    index.set_bookmarked(&haystack.bookmarked);
+
+    let pending = index.unembedded();
+    let embedding = embed_config(indexing);
+    if indexing.dry_run {
+        println!("{}", EmbedEstimate::new(&pending, &embedding));
+        return Ok(index);
+    }
+    if !pending.is_empty() {
+        info!("Embedding {} new paragraphs...", pending.len());
+        stats.embedded = pending.len();
+        let embeddings = embed_documents(pending, &proxy, &embedding)
+            .await
+            .inspect_err(|e| error!("Failed to embed webpages: {e}"))?;
+        index.add_embeddings(embeddings);
+    }
+    stats.pruned = index.prune_embeddings();
+    index.save(&path)?;
+
+    info!(
+        "Indexed {} pages ({} added, {} changed, {} unchanged, {} removed); embedded {} paragraphs and pruned {}",
+        index.pages.len(),
+        stats.added,
+        stats.changed,
+        stats.unchanged,
+        stats.removed,
+        stats.embedded,
+        stats.pruned
+    );
+    Ok(index)
+}
+
+/// Search the persistent index after bringing it up to date
+async fn search_index(
+    path: &Path,
+    prompt: &str,
+    opts: &Opts,
+) -> eyre::Result<()> {
+    let haystack = load_haystack(path, &opts.common)?;
+    let index = update_index(&haystack, &opts.indexing, &opts.common).await?;
+    if opts.indexing.dry_run {
+        return Ok(());
+    }
+
+    let urls: HashSet<&Url> = haystack.urls.iter().collect();
+    let dates = DateRange {
+        since: opts.common.since,
+        until: opts.common.until,
+    };
+    let embedded = index.embedded_docs(|url, doc| {
+        urls.contains(url)
+            && (!dates.is_bounded()
+                || doc
+                    .bookmarked
+                    .or(doc.published)
+                    .is_some_and(|t| dates.contains(&t)))
+    });
+    info!("Searching {} indexed documents", embedded.len());
+
+    let proxy = ProxyConfig::from_env_or(
+        opts.common.proxy.clone(),
+        opts.common.no_proxy.clone(),
+    );
+    let rag = RagStore::from_embeddings(embedded, &proxy)?;
+    print_findings(&rag, prompt).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::parse_timestamp;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_warc_haystack() {
+        let parse = |args: &[&str]| {
+            Opts::try_parse_from(
+                ["slurpsearch", "pages.warc.gz", "prompt"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap()
+        };
+
+        for args in [&["--incremental"][..], &["--warc-out", "out.warc"]] {
+            let err = search(parse(args)).await.unwrap_err();
+            assert!(err.to_string().contains("can't be used with a WARC file"));
+        }
+
+        let opts = parse(&[]);
+        let Err(err) = load_haystack(Path::new("pages.warc.gz"), &opts.common)
+        else {
+            panic!("a WARC file loaded as a haystack");
+        };
+        assert!(err.to_string().contains("is a WARC file"));
+    }
+
+    #[test]
+    fn test_bookmark_date() {
+        let saved = parse_timestamp("2023-05-01").unwrap();
+        let moved = parse_timestamp("2024-01-01").unwrap();
+        let bookmarked = HashMap::from([
+            (url("https://blog.example/"), saved),
+            (url("https://new.example/post"), moved),
+        ]);
+        let date = |requested: &str, found: &str, seed: &str| {
+            bookmark_date(&bookmarked, &url(requested), &url(found), &url(seed))
+        };
+
+        /* the bookmark itself */
+        assert_eq!(
+            date(
+                "https://blog.example/",
+                "https://blog.example/",
+                "https://blog.example/"
+            ),
+            Some(saved)
+        );
+        /* a bookmark that redirected */
+        assert_eq!(
+            date(
+                "https://old.example/post",
+                "https://new.example/post",
+                "https://old.example/post"
+            ),
+            Some(moved)
+        );
+        /* a page crawled or discovered from a bookmark */
+        assert_eq!(
+            date(
+                "https://blog.example/2023/post",
+                "https://blog.example/2023/post",
+                "https://blog.example/"
+            ),
+            Some(saved)
+        );
+        assert_eq!(
+            date(
+                "https://other.example/",
+                "https://other.example/",
+                "https://other.example/"
+            ),
+            None
+        );
+    }
+}
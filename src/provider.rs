@@ -0,0 +1,115 @@
+use rig::{
+    client::Nothing,
+    embeddings::{Embedding, EmbeddingError, EmbeddingModel},
+};
+use sha2::{Digest, Sha256};
+
+/// Default number of dimensions of [`HashEmbedder`] vectors
+pub const DEFAULT_HASH_DIMENSIONS: usize = 256;
+
+/// Offline embedding model that hashes each word of a text into one of a
+/// fixed number of dimensions
+///
+/// Vectors are deterministic and texts sharing words are similar, which is
+/// all that tests (and dry runs of the pipeline) need.
+#[derive(Copy, Clone, Debug)]
+pub struct HashEmbedder {
+    pub ndims: usize,
+}
+
+impl Default for HashEmbedder {
+    fn default() -> Self {
+        Self {
+            ndims: DEFAULT_HASH_DIMENSIONS,
+        }
+    }
+}
+
+impl HashEmbedder {
+    /// Unit vector for `text`, or all zeroes if it has no words
+    pub fn vector(&self, text: &str) -> Vec<f64> {
+        let ndims = self.ndims.max(1);
+        let mut vec = vec![0.0; ndims];
+
+        for word in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            let digest = Sha256::digest(word.to_lowercase().as_bytes());
+            let bucket = u64::from_le_bytes(
+                digest[..8].try_into().expect("digest is 32 bytes"),
+            );
+            let sign = if digest[8] & 1 == 0 { 1.0 } else { -1.0 };
+            vec[(bucket % ndims as u64) as usize] += sign;
+        }
+
+        let norm = vec.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            vec.iter_mut().for_each(|x| *x /= norm);
+        }
+        vec
+    }
+}
+
+impl EmbeddingModel for HashEmbedder {
+    const MAX_DOCUMENTS: usize = 1024;
+
+    type Client = Nothing;
+
+    fn make(_: &Nothing, _: impl Into<String>, dims: Option<usize>) -> Self {
+        Self {
+            ndims: dims.unwrap_or(DEFAULT_HASH_DIMENSIONS),
+        }
+    }
+
+    fn ndims(&self) -> usize {
+        self.ndims
+    }
+
+    async fn embed_texts(
+        &self,
+        texts: impl IntoIterator<Item = String>,
+    ) -> Result<Vec<Embedding>, EmbeddingError> {
+        Ok(texts
+            .into_iter()
+            .map(|text| Embedding {
+                vec: self.vector(&text),
+                document: text,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cosine(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[tokio::test]
+    async fn test_hash_embedder() {
+        let embedder = HashEmbedder::default();
+        let embeddings = embedder
+            .embed_texts(vec![
+                "Rust borrow checker".to_string(),
+                "the borrow checker in rust".to_string(),
+                "sourdough baking".to_string(),
+                "".to_string(),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(embeddings[0].vec, embedder.vector("rust BORROW checker"));
+        assert_eq!(embeddings[0].vec.len(), DEFAULT_HASH_DIMENSIONS);
+        assert!(
+            (cosine(&embeddings[0].vec, &embeddings[0].vec) - 1.0).abs() < 1e-9
+        );
+        assert!(
+            cosine(&embeddings[0].vec, &embeddings[1].vec)
+                > cosine(&embeddings[0].vec, &embeddings[2].vec)
+        );
+        assert!(embeddings[3].vec.iter().all(|x| *x == 0.0));
+    }
+}
@@ -1,38 +1,29 @@
 use std::fmt::Display;
 
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use rig::Embed;
-use rig::agent::Agent;
-use rig::client::CompletionClient;
-use rig::providers::openai::responses_api::ResponsesCompletionModel;
+use rig::OneOrMany;
+use rig::embeddings::{Embedding, EmbeddingModel};
 use rig::vector_store::VectorStoreIndex;
 use rig::vector_store::in_memory_store::InMemoryVectorIndex;
 use rig::vector_store::request::VectorSearchRequestBuilder;
 use rig::{
-    client::{EmbeddingsClient, ProviderClient},
-    embeddings::EmbeddingsBuilder,
-    providers::openai,
+    client::EmbeddingsClient, providers::openai,
     vector_store::in_memory_store::InMemoryVectorStore,
 };
 use serde::Deserialize;
 use serde::Serialize;
 use url::Url;
 
-use crate::search::Finding;
-
-/// Name of the model to use for inference
-const COMPLETION_MODEL: &str = "gpt-5.2";
+use crate::{
+    dates::Timestamp,
+    embed::{EmbedConfig, embed_batched},
+    http::ProxyConfig,
+    search::{Finding, TextPosition},
+};
 
 /// Name of the model to use for text embeddings
-const EMBEDDING_MODEL: &str = "text-embedding-3-large";
-
-/// String to prefix query prompts with
-const INSTRUCTIONS: &str = r#"Find the most relevant documents based on the following query. Respond only with valid JSON. Respond with a list of JSON objects of the form:
-
- - `document_id`: the unique identifier of the document
- - `relevance`: how relevant the document is to the query (as an integer in the inclusive range 0-100)
- - `reason`: justification for why this is the case
-"#;
+pub const EMBEDDING_MODEL: &str = "text-embedding-3-large";
 
 /// Represents a document within the RAG system
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, rig::Embed)]
@@ -40,30 +31,88 @@ pub struct WebDoc {
     pub url: Url,
     #[embed]
     pub text: String,
+    /// Where in the page's extracted text this document begins
+    pub position: TextPosition,
+    /// `id` of the nearest element we can link to directly, if any
+    pub anchor: Option<String>,
+    /// What sort of content the text was extracted from
+    pub kind: ContentKind,
+    /// Haystack URL this page was crawled from, if not the page itself
+    pub seed: Option<Url>,
+    /// When the page was bookmarked, according to the haystack
+    pub bookmarked: Option<Timestamp>,
+    /// When the page says it was published
+    pub published: Option<Timestamp>,
+    /// Archived snapshot the text came from, if the page itself couldn't be
+    /// fetched
+    pub archived: Option<Url>,
 }
 
-/// Represents a search result returned from the model
-#[derive(Clone, Debug, Deserialize)]
-pub struct SearchResult {
-    pub document_id: String,
-    pub relevance: u64,
-    pub reason: String,
+/// Broad category of the markup a [`WebDoc`] was extracted from
+#[derive(
+    Copy, Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentKind {
+    /// Running text such as paragraphs, list items and headings
+    #[default]
+    Prose,
+    /// Rows of a table or entries of a definition list
+    Tabular,
 }
 
-impl Display for SearchResult {
+impl Display for ContentKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: {}%", self.document_id, self.relevance)
+        match self {
+            Self::Prose => write!(f, "prose"),
+            Self::Tabular => write!(f, "tabular"),
+        }
     }
 }
 
 /// Uniquely identifies a given document for the purposes of embedding
 pub type DocumentId = String;
 
+/// OpenAI client configured from the environment (like
+/// [`rig::client::ProviderClient::from_env`]) that talks through `proxy`
+fn openai_client(proxy: &ProxyConfig) -> Result<openai::Client> {
+    let api_key =
+        std::env::var("OPENAI_API_KEY").wrap_err("OPENAI_API_KEY not set")?;
+    let http = proxy
+        .apply(reqwest::Client::builder())?
+        .build()
+        .wrap_err("failed to build embedding HTTP client")?;
+
+    let mut builder = openai::Client::<reqwest::Client>::builder()
+        .api_key(&api_key)
+        .http_client(http);
+    if let Ok(base_url) = std::env::var("OPENAI_BASE_URL") {
+        builder = builder.base_url(&base_url);
+    }
+
+    builder.build().wrap_err("failed to build OpenAI client")
+}
+
+/// Embed documents via remote calls to [`EMBEDDING_MODEL`]
+pub async fn embed_documents(
+    documents: Vec<WebDoc>,
+    proxy: &ProxyConfig,
+    config: &EmbedConfig,
+) -> Result<Vec<(WebDoc, OneOrMany<Embedding>)>> {
+    let client = openai_client(proxy)?;
+    let embedding_model = client.embedding_model(EMBEDDING_MODEL);
+
+    /* NOTE(jmcph4): actual requests fly out the door here */
+    embed_batched(&embedding_model, documents, config).await
+}
+
+/// Documents embedded for searching, along with the model used to search
+/// them
 #[derive(Clone)]
-pub struct RagStore {
-    pub client: openai::Client,
+pub struct RagStore<E: EmbeddingModel = openai::EmbeddingModel> {
     pub store: InMemoryVectorStore<WebDoc>,
-    pub model: openai::EmbeddingModel,
+    /// Model the documents were embedded with, which queries must be too
+    pub model: E,
 }
 
 impl RagStore {
@@ -72,44 +121,59 @@ impl RagStore {
     /// Constructs [`WebDoc`]s from the provided (URL, contents) pairs, embeds
     /// them (via remote calls to [`EMBEDDING_MODEL`]), and inserts these
     /// embeddings into the vector store.
-    pub async fn try_from_documents(documents: Vec<WebDoc>) -> Result<Self> {
-        let client = openai::Client::from_env();
-
-        let embedding_model = client.embedding_model(EMBEDDING_MODEL);
-        /* NOTE(jmcph4): actual request flies out the door here */
-        let embeddings = EmbeddingsBuilder::new(embedding_model.clone())
-            .documents(documents.clone())?
-            .build()
-            .await?;
-
-        Ok(Self {
-            client,
+    pub async fn try_from_documents(
+        documents: Vec<WebDoc>,
+        proxy: &ProxyConfig,
+        config: &EmbedConfig,
+    ) -> Result<Self> {
+        let embeddings = embed_documents(documents, proxy, config).await?;
+        Self::from_embeddings(embeddings, proxy)
+    }
+
+    /// Build a [`RagStore`] from documents that have already been embedded
+    /// with [`EMBEDDING_MODEL`]
+    pub fn from_embeddings(
+        embeddings: Vec<(WebDoc, OneOrMany<Embedding>)>,
+        proxy: &ProxyConfig,
+    ) -> Result<Self> {
+        let client = openai_client(proxy)?;
+        Ok(Self::new(
+            embeddings,
+            client.embedding_model(EMBEDDING_MODEL),
+        ))
+    }
+}
+
+impl<E: EmbeddingModel + Clone> RagStore<E> {
+    /// Build a [`RagStore`] from documents already embedded with `model`
+    pub fn new(
+        embeddings: Vec<(WebDoc, OneOrMany<Embedding>)>,
+        model: E,
+    ) -> Self {
+        Self {
             store: InMemoryVectorStore::from_documents(embeddings),
-            model: embedding_model,
-        })
+            model,
+        }
     }
 
-    pub fn index(&self) -> InMemoryVectorIndex<openai::EmbeddingModel, WebDoc> {
-        self.store.clone().index(self.model.clone())
+    /// Embed `documents` with `model` and build a [`RagStore`] from them
+    pub async fn from_documents(
+        documents: Vec<WebDoc>,
+        model: E,
+        config: &EmbedConfig,
+    ) -> Result<Self> {
+        let embeddings = embed_batched(&model, documents, config).await?;
+        Ok(Self::new(embeddings, model))
     }
 
-    /// Return a handle to the completion model
-    pub fn agent(&self) -> Agent<ResponsesCompletionModel> {
-        self.client
-            .agent(COMPLETION_MODEL)
-            .preamble(INSTRUCTIONS)
-            .dynamic_context(self.store.len(), self.index())
-            .build()
+    pub fn index(&self) -> InMemoryVectorIndex<E, WebDoc> {
+        self.store.clone().index(self.model.clone())
     }
 
     /// Search the document store
     ///
-    /// Returns [`SearchResult`]s in descending order of relevance.
-    pub async fn search(
-        &self,
-        query: &str,
-        relevance_threshold: Option<f64>,
-    ) -> eyre::Result<Vec<Finding>> {
+    /// Returns [`Finding`]s in descending order of relevance.
+    pub async fn search(&self, query: &str) -> eyre::Result<Vec<Finding>> {
         let search_request = VectorSearchRequestBuilder::default()
             .query(query)
             .samples(self.store.len() as u64);
@@ -129,3 +193,60 @@ impl RagStore {
         Ok(findings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::{ChunkConfig, chunk_docs},
+        provider::HashEmbedder,
+        sites::ExtractorRegistry,
+    };
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let pages = [
+            (
+                "https://x.com/borrowck",
+                "<main><h1>Borrow checking</h1><p>The Rust borrow checker rejects programs that alias mutable references.</p></main>",
+            ),
+            (
+                "https://y.com/sourdough",
+                "<main><p>Feed the sourdough starter with flour and water before baking bread.</p></main>",
+            ),
+        ];
+        let extractors = ExtractorRegistry::default();
+        let docs: Vec<WebDoc> = pages
+            .iter()
+            .flat_map(|(url, html)| {
+                let docs =
+                    extractors.extract(Url::parse(url).unwrap(), html).unwrap();
+                chunk_docs(docs, &ChunkConfig::default())
+            })
+            .collect();
+        assert_eq!(docs.len(), 2);
+
+        let config = EmbedConfig {
+            batch_size: 1,
+            ..Default::default()
+        };
+        let store =
+            RagStore::from_documents(docs, HashEmbedder::default(), &config)
+                .await
+                .unwrap();
+        assert_eq!(store.store.len(), 2);
+
+        let query = "what does the rust borrow checker reject";
+        let findings = store.search(query).await.unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].doc.url.as_str(), "https://x.com/borrowck");
+        assert!(findings[0].doc.text.contains("borrow checker rejects"));
+        assert_eq!(findings[1].doc.url.as_str(), "https://y.com/sourdough");
+        assert!(
+            findings
+                .windows(2)
+                .all(|w| w[0].relevance >= w[1].relevance)
+        );
+        assert!(findings.iter().all(|x| x.search == query));
+    }
+}
@@ -0,0 +1,360 @@
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::OnceCell,
+    time::{Instant, sleep_until},
+};
+use tracing::debug;
+use url::Url;
+
+/// Product token we identify as when matching `User-agent` lines
+pub const ROBOTS_AGENT: &str = env!("CARGO_PKG_NAME");
+
+/// Upper bound on honoured `Crawl-delay`s so one site can't stall a run
+const MAX_CRAWL_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+/// A parsed `robots.txt` file
+///
+/// Follows RFC 9309: rules come from every group naming our user agent (or
+/// from the `*` groups if none do), the longest matching pattern wins and
+/// `Allow` beats `Disallow` on a tie.
+#[derive(Clone, Debug, Default)]
+pub struct Robots {
+    groups: Vec<Group>,
+    /// Set when the file couldn't be retrieved due to a server or network
+    /// error
+    disallow_all: bool,
+    /// Why the file couldn't be retrieved, if it was a network error
+    unreachable: Option<String>,
+}
+
+impl Robots {
+    pub fn parse(body: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agents {
+                        groups.push(Group::default());
+                        in_agents = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_ascii_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agents = false;
+                    if let Some(group) = groups.last_mut()
+                        && !value.is_empty()
+                    {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    in_agents = false;
+                    if let Some(group) = groups.last_mut() {
+                        group.crawl_delay = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                            .map(Duration::from_secs_f64);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            groups,
+            disallow_all: false,
+            unreachable: None,
+        }
+    }
+
+    /// Policy used when `robots.txt` exists but can't be read
+    pub fn disallow_all() -> Self {
+        Self {
+            groups: vec![],
+            disallow_all: true,
+            unreachable: None,
+        }
+    }
+
+    /// Policy used when the server couldn't be reached at all, for `reason`
+    pub fn unreachable(reason: String) -> Self {
+        Self {
+            unreachable: Some(reason),
+            ..Self::disallow_all()
+        }
+    }
+
+    /// Why the server couldn't be reached, if it couldn't
+    pub fn unreachable_reason(&self) -> Option<&str> {
+        self.unreachable.as_deref()
+    }
+
+    /// Groups naming `agent`'s product token exactly (ignoring case), or
+    /// failing that the `*` groups
+    fn groups_for(&self, agent: &str) -> Vec<&Group> {
+        let named: Vec<&Group> = self
+            .groups
+            .iter()
+            .filter(|g| g.agents.iter().any(|a| a.eq_ignore_ascii_case(agent)))
+            .collect();
+
+        if !named.is_empty() {
+            return named;
+        }
+
+        self.groups
+            .iter()
+            .filter(|g| g.agents.iter().any(|a| a == "*"))
+            .collect()
+    }
+
+    /// Whether `agent` may fetch `url`
+    pub fn is_allowed(&self, agent: &str, url: &Url) -> bool {
+        if self.disallow_all {
+            return false;
+        }
+
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+
+        /* robots.txt itself is always allowed */
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        self.groups_for(agent)
+            .into_iter()
+            .flat_map(|g| g.rules.iter())
+            .filter(|rule| matches(&rule.pattern, &path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+
+    /// Minimum interval `agent` should leave between requests, if specified
+    pub fn crawl_delay(&self, agent: &str) -> Option<Duration> {
+        self.groups_for(agent)
+            .into_iter()
+            .filter_map(|g| g.crawl_delay)
+            .max()
+            .map(|delay| delay.min(MAX_CRAWL_DELAY))
+    }
+}
+
+/// Match a `robots.txt` path pattern (supporting `*` and a trailing `$`)
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchor) = match pattern.strip_suffix('$') {
+        Some(p) => (p, "$"),
+        None => (pattern, ""),
+    };
+    let body: Vec<String> = pattern.split('*').map(regex::escape).collect();
+
+    Regex::new(&format!("^{}{anchor}", body.join(".*")))
+        .is_ok_and(|re| re.is_match(path))
+}
+
+/// Per-origin cache of `robots.txt` files that also paces requests
+pub struct RobotsCache {
+    client: reqwest::Client,
+    agent: String,
+    files: Mutex<HashMap<String, Arc<OnceCell<Arc<Robots>>>>>,
+    /// Earliest time the next request to each origin may be made
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl RobotsCache {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            agent: ROBOTS_AGENT.to_string(),
+            files: Mutex::new(HashMap::new()),
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The `robots.txt` in force for `url`, retrieving it if necessary
+    pub async fn get(&self, url: &Url) -> Arc<Robots> {
+        let origin = url.origin().ascii_serialization();
+
+        /* one cell per origin so concurrent callers share a single request */
+        let cell =
+            Arc::clone(self.files.lock().unwrap().entry(origin).or_default());
+
+        Arc::clone(
+            cell.get_or_init(|| async { Arc::new(self.fetch(url).await) })
+                .await,
+        )
+    }
+
+    async fn fetch(&self, url: &Url) -> Robots {
+        let Ok(robots_url) = url.join("/robots.txt") else {
+            return Robots::default();
+        };
+
+        match self.client.get(robots_url.clone()).send().await {
+            Ok(res) if res.status().is_success() => {
+                Robots::parse(&res.text().await.unwrap_or_default())
+            }
+            Ok(res) if res.status().is_server_error() => {
+                debug!("{robots_url} returned {}", res.status());
+                Robots::disallow_all()
+            }
+            Ok(_) => Robots::default(),
+            /* RFC 9309 treats an unreachable robots.txt like a server error */
+            Err(e) => {
+                let reason = std::iter::successors(
+                    Some(&e as &dyn std::error::Error),
+                    |e| e.source(),
+                )
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(": ");
+                debug!("failed to retrieve {robots_url}: {reason}");
+                Robots::unreachable(reason)
+            }
+        }
+    }
+
+    pub async fn is_allowed(&self, url: &Url) -> bool {
+        self.get(url).await.is_allowed(&self.agent, url)
+    }
+
+    /// Wait until the origin's `Crawl-delay` permits another request
+    pub async fn wait_turn(&self, url: &Url) {
+        let Some(delay) = self.get(url).await.crawl_delay(&self.agent) else {
+            return;
+        };
+        let origin = url.origin().ascii_serialization();
+
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.get(&origin).copied().unwrap_or(now).max(now);
+            next_slot.insert(origin, slot + delay);
+            slot
+        };
+
+        sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(path: &str) -> Url {
+        Url::parse("https://example.com")
+            .unwrap()
+            .join(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_longest_match_wins() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /private/\nAllow: /private/public/\n",
+        );
+        assert!(robots.is_allowed("slurpsearch", &url("/")));
+        assert!(!robots.is_allowed("slurpsearch", &url("/private/a")));
+        assert!(robots.is_allowed("slurpsearch", &url("/private/public/a")));
+    }
+
+    #[test]
+    fn test_user_agent_groups() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\n\
+             User-agent: SlurpSearch\nUser-agent: otherbot\nDisallow: /drafts\nCrawl-delay: 2\n",
+        );
+        assert!(robots.is_allowed("slurpsearch", &url("/posts/1")));
+        assert!(!robots.is_allowed("slurpsearch", &url("/drafts/1")));
+        assert!(!robots.is_allowed("somebot", &url("/posts/1")));
+        assert_eq!(
+            robots.crawl_delay("slurpsearch"),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(robots.crawl_delay("somebot"), None);
+    }
+
+    #[test]
+    fn test_exact_agent_match() {
+        /* Yahoo's crawler, not us, despite the shared prefix */
+        let robots = Robots::parse(
+            "User-agent: Slurp
+Disallow: /
+
+             User-agent: search
+Disallow: /
+
+             User-agent: *
+Disallow: /private
+Crawl-delay: 1
+",
+        );
+        assert!(robots.is_allowed("slurpsearch", &url("/posts/1")));
+        assert!(!robots.is_allowed("slurpsearch", &url("/private/a")));
+        assert_eq!(
+            robots.crawl_delay("slurpsearch"),
+            Some(Duration::from_secs(1))
+        );
+        assert!(!robots.is_allowed("slurp", &url("/posts/1")));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /*.pdf$\nDisallow: /*?s=\n",
+        );
+        assert!(!robots.is_allowed("slurpsearch", &url("/a/b.pdf")));
+        assert!(robots.is_allowed("slurpsearch", &url("/a/b.pdf.html")));
+        assert!(!robots.is_allowed("slurpsearch", &url("/search?s=rust")));
+        assert!(robots.is_allowed("slurpsearch", &url("/search")));
+    }
+
+    #[test]
+    fn test_empty_and_unreachable() {
+        assert!(Robots::parse("").is_allowed("slurpsearch", &url("/a")));
+        assert!(
+            Robots::parse("User-agent: *\nDisallow:\n")
+                .is_allowed("slurpsearch", &url("/a"))
+        );
+        assert!(!Robots::disallow_all().is_allowed("slurpsearch", &url("/a")));
+
+        let unreachable = Robots::unreachable("dns error".to_string());
+        assert!(!unreachable.is_allowed("slurpsearch", &url("/a")));
+        assert_eq!(unreachable.unreachable_reason(), Some("dns error"));
+    }
+}
@@ -1,13 +1,20 @@
-use crate::rag::WebDoc;
+use crate::{
+    dates::{Timestamp, parse_timestamp},
+    rag::{ContentKind, WebDoc},
+    tokenize::UrlTokenizer,
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashSet, fmt::Display};
 use url::Url;
 
-/// Default minimum relevance for a [`Finding`] to be returned to the end user
-pub const DEFAULT_RELEVANCE_THRESHOLD: f64 = 0.60; /* 60% */
-
-#[derive(Copy, Clone, Debug, Serialize)]
+/// Location of a document within the text extracted from its page
+///
+/// Each extracted block (paragraph, list item, heading, etc.) of a page is
+/// treated as a line, so `line` is the 1-based index of the block in document
+/// order and `column` is the 1-based character offset within that block.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct TextPosition {
     pub line: usize,
     pub column: usize,
@@ -30,30 +37,250 @@ pub struct Finding {
     pub doc: WebDoc,
 }
 
+/// Number of words used for each end of a text fragment
+const TEXT_FRAGMENT_WORDS: usize = 5;
+
+/// Characters left unescaped in text fragment directives
+///
+/// Everything else is percent-encoded, notably `-`, `,` and `&` which are
+/// syntactically significant within `:~:text=`.
+const TEXT_FRAGMENT_SAFE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b';')
+    .remove(b'/')
+    .remove(b'?')
+    .remove(b'@');
+
+impl Finding {
+    /// Link that jumps straight to the matched text when opened in a browser
+    ///
+    /// Uses a [text fragment](https://wicg.github.io/scroll-to-text-fragment/)
+    /// built from the first and last few words of the document, prefixed by
+    /// the nearest element `id` (if any), which browsers scroll to instead if
+    /// the text can't be found. Browsers without text fragment support see a
+    /// single fragment matching no element and just open the page. Tabular
+    /// documents are rows joined with separators that never appear on the
+    /// page, so they only get the anchor.
+    pub fn link(&self) -> Url {
+        /* a dead page can only be read in the archive */
+        let mut url =
+            self.doc.archived.as_ref().unwrap_or(&self.doc.url).clone();
+        let anchor = self.doc.anchor.as_deref().unwrap_or_default();
+        let words: Vec<&str> = self.doc.text.split_whitespace().collect();
+
+        if words.is_empty() || self.doc.kind != ContentKind::Prose {
+            url.set_fragment((!anchor.is_empty()).then_some(anchor));
+            return url;
+        }
+
+        let encode = |ws: &[&str]| {
+            utf8_percent_encode(&ws.join(" "), TEXT_FRAGMENT_SAFE).to_string()
+        };
+
+        let directive = if words.len() <= TEXT_FRAGMENT_WORDS * 2 {
+            encode(&words)
+        } else {
+            format!(
+                "{},{}",
+                encode(&words[..TEXT_FRAGMENT_WORDS]),
+                encode(&words[words.len() - TEXT_FRAGMENT_WORDS..])
+            )
+        };
+
+        url.set_fragment(Some(&format!("{anchor}:~:text={directive}")));
+        url
+    }
+}
+
 impl Display for Finding {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "URL: {}", self.doc.url)?;
+        writeln!(f, "Link: {}", self.link())?;
+        if let Some(seed) = &self.doc.seed {
+            writeln!(f, "Via: {seed}")?;
+        }
+        if let Some(bookmarked) = &self.doc.bookmarked {
+            writeln!(f, "Bookmarked: {bookmarked}")?;
+        }
+        if let Some(published) = &self.doc.published {
+            writeln!(f, "Published: {published}")?;
+        }
+        if let Some(archived) = &self.doc.archived {
+            writeln!(f, "Archived: {archived}")?;
+        }
+        writeln!(f, "Location: {} ({})", self.doc.position, self.doc.kind)?;
         writeln!(f, "Text: {}", self.doc.text)?;
         writeln!(f, "Relevance: {}%", self.relevance * 100.0)?;
         Ok(())
     }
 }
 
-/// Set of characters to strip from URLs
-const TRIM: &[char] = &[')', ']', '}', '.', ',', ';', ':', '"', '\'', '>', ' '];
+/// Regular expression for the bracketed timestamps bookmarks are saved with,
+/// e.g. `[2025-12-12T17:18:12+10:00]`
+const TIMESTAMP_REGEX: &str =
+    r"\[(\d{4}-\d{2}-\d{2}[T ][0-9:.]+(?:Z|[+-]\d{2}:?\d{2})?)\]";
 
-/// Regular expression for extracting URLs from text
-const URL_EXTRACTION_REGEX: &str =
-    r#"https?://[A-Za-z0-9\-._~:/?#\[\]@!$&'()*+,;=%]+"#;
+/// A URL in the haystack along with when it was bookmarked, if known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bookmark {
+    pub url: Url,
+    pub bookmarked: Option<Timestamp>,
+}
+
+/// Return every URL in the provided string, in order of appearance
+///
+/// Each URL is dated by the timestamp closest before it on the same line or,
+/// failing that, any timestamp on its line.
+pub fn extract_bookmarks(s: &str) -> Vec<Bookmark> {
+    let tokenizer = UrlTokenizer::default();
+    let timestamp_re = Regex::new(TIMESTAMP_REGEX).unwrap();
+
+    s.lines()
+        .flat_map(|line| {
+            let timestamps: Vec<(usize, Timestamp)> = timestamp_re
+                .captures_iter(line)
+                .filter_map(|c| {
+                    Some((c.get(0)?.end(), parse_timestamp(&c[1])?))
+                })
+                .collect();
+
+            tokenizer
+                .tokenize(line)
+                .into_iter()
+                .map(move |(start, url)| {
+                    let bookmarked = timestamps
+                        .iter()
+                        .rev()
+                        .find(|(end, _)| *end <= start)
+                        .or(timestamps.first())
+                        .map(|(_, t)| *t);
+
+                    Bookmark { url, bookmarked }
+                })
+        })
+        .collect()
+}
 
 /// Return the set of all URLs from the provided string
 ///
-/// Note that the URLs are not guaranteed to be of the HTTP nor HTTPS domain.
+/// See [`UrlTokenizer`] for what counts as a URL.
 pub fn extract_urls(s: &str) -> HashSet<Url> {
-    let re = Regex::new(URL_EXTRACTION_REGEX).unwrap();
+    extract_bookmarks(s).into_iter().map(|b| b.url).collect()
+}
 
-    re.find_iter(s)
-        .map(|m| m.as_str().trim_end_matches(TRIM))
-        .filter_map(|m| Url::parse(m).ok())
-        .collect()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_urls() {
+        let haystack = "see www.example.com, example.org/post?id=1 and <docs.rs/tokio>\n\
+                        local: file:///home/me/notes/page.html\n\
+                        not urls: README.md src/main.rs me@example.com v1.2.3 e.g. foo\n\
+                        https://www.example.net/a only once\n";
+        let mut urls: Vec<String> = extract_urls(haystack)
+            .into_iter()
+            .map(|url| url.to_string())
+            .collect();
+        urls.sort();
+
+        assert_eq!(
+            urls,
+            vec![
+                "file:///home/me/notes/page.html",
+                "https://docs.rs/tokio",
+                "https://example.org/post?id=1",
+                "https://www.example.com/",
+                "https://www.example.net/a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_bookmarks() {
+        let haystack = " - [2025-12-12T17:18:12+10:00] [Scatterpad](https://scatterpad.com)\n\
+                        ---\n\
+                        https://undated.example/post\n\
+                        [2025-12-13T19:00:33+10:00] https://a.example/ and https://b.example/\n";
+        let bookmarks = extract_bookmarks(haystack);
+        let dated = |s: &str| parse_timestamp(s);
+
+        assert_eq!(
+            bookmarks,
+            vec![
+                Bookmark {
+                    url: Url::parse("https://scatterpad.com").unwrap(),
+                    bookmarked: dated("2025-12-12T17:18:12+10:00"),
+                },
+                Bookmark {
+                    url: Url::parse("https://undated.example/post").unwrap(),
+                    bookmarked: None,
+                },
+                Bookmark {
+                    url: Url::parse("https://a.example/").unwrap(),
+                    bookmarked: dated("2025-12-13T19:00:33+10:00"),
+                },
+                Bookmark {
+                    url: Url::parse("https://b.example/").unwrap(),
+                    bookmarked: dated("2025-12-13T19:00:33+10:00"),
+                },
+            ]
+        );
+    }
+
+    fn finding(text: &str, anchor: Option<&str>, kind: ContentKind) -> Finding {
+        Finding {
+            search: "query".to_string(),
+            relevance: 0.9,
+            doc: WebDoc {
+                url: Url::parse("https://x.com/post").unwrap(),
+                text: text.to_string(),
+                position: TextPosition { line: 1, column: 1 },
+                anchor: anchor.map(str::to_string),
+                kind,
+                seed: None,
+                bookmarked: None,
+                published: None,
+                archived: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        let long = finding(
+            "Salt & pepper, mixed - then knead it well until smooth and elastic",
+            Some("method"),
+            ContentKind::Prose,
+        );
+        assert_eq!(
+            long.link().as_str(),
+            "https://x.com/post#method:~:text=Salt%20%26%20pepper%2C%20mixed%20%2D,well%20until%20smooth%20and%20elastic"
+        );
+
+        /* ten words or fewer are matched whole */
+        let short =
+            finding("Fixed in 1.2.1 (see #42)", None, ContentKind::Prose);
+        assert_eq!(
+            short.link().as_str(),
+            "https://x.com/post#:~:text=Fixed%20in%201.2.1%20(see%20%2342)"
+        );
+
+        /* rows never appear verbatim on the page, so only the anchor helps */
+        let row =
+            finding("Version | Fixed", Some("versions"), ContentKind::Tabular);
+        assert_eq!(row.link().as_str(), "https://x.com/post#versions");
+        let row = finding("Version | Fixed", None, ContentKind::Tabular);
+        assert_eq!(row.link().as_str(), "https://x.com/post");
+
+        let empty = finding("  ", Some("top"), ContentKind::Prose);
+        assert_eq!(empty.link().as_str(), "https://x.com/post#top");
+    }
 }
@@ -0,0 +1,346 @@
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use eyre::WrapErr;
+use playwright::api::{Cookie, OriginState, StorageState};
+use url::Url;
+
+use crate::sites::HostPattern;
+
+/// A request header, optionally restricted to certain hosts
+///
+/// Written as `NAME: VALUE` to send it everywhere or `HOST=NAME: VALUE` to
+/// send it only to hosts matching `HOST` (e.g., `*.corp.example=Authorization:
+/// Bearer abc`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostHeader {
+    pub hosts: Option<HostPattern>,
+    pub name: String,
+    pub value: String,
+}
+
+impl HostHeader {
+    /// Header `name` for hosts matching the `[HOST=]VALUE` in `s`
+    pub fn parse_with_name(name: &str, s: &str) -> Self {
+        let (hosts, value) = split_host(s);
+        Self {
+            hosts,
+            name: name.to_string(),
+            value: value.trim().to_string(),
+        }
+    }
+
+    fn applies_to(&self, host: &str) -> bool {
+        self.hosts.as_ref().is_none_or(|p| p.matches(host))
+    }
+}
+
+impl FromStr for HostHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hosts, header) = split_host(s);
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("expected [HOST=]NAME: VALUE, got `{s}`"))?;
+
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid header name `{name}`"));
+        }
+
+        Ok(Self {
+            hosts,
+            name: name.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+}
+
+/// Split an optional `HOST=` prefix from `s`
+///
+/// Header values may well contain `=` themselves, so the prefix only counts
+/// if it looks like a host pattern.
+fn split_host(s: &str) -> (Option<HostPattern>, &str) {
+    match s.split_once('=') {
+        Some((host, rest))
+            if !host.is_empty()
+                && !host.contains(|c: char| {
+                    c.is_whitespace() || c == ':' || c == '/'
+                }) =>
+        {
+            (Some(HostPattern::new(host)), rest)
+        }
+        _ => (None, s),
+    }
+}
+
+/// Cookies, local storage and headers that let us fetch pages as a
+/// logged-in user
+#[derive(Clone, Debug, Default)]
+pub struct SessionConfig {
+    pub cookies: Vec<Cookie>,
+    /// Local storage to populate, from a Playwright storage state
+    pub origins: Vec<OriginState>,
+    /// Extra headers, including any per-host `User-Agent`s
+    pub headers: Vec<HostHeader>,
+    /// User agent for hosts without a `User-Agent` header of their own
+    pub user_agent: Option<String>,
+}
+
+impl SessionConfig {
+    /// Add the cookies (and local storage) saved in `path`
+    ///
+    /// Accepts either a Playwright storage state (as written by
+    /// `context.storageState()`) or a Netscape `cookies.txt`, as exported by
+    /// curl and most browser extensions.
+    pub fn load_cookies(&mut self, path: &Path) -> eyre::Result<()> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+
+        if contents.trim_start().starts_with('{') {
+            let state: StorageState = serde_json::from_str(&contents)
+                .wrap_err_with(|| {
+                    format!("invalid storage state in {}", path.display())
+                })?;
+            self.cookies.extend(state.cookies.unwrap_or_default());
+            self.origins.extend(state.origins.unwrap_or_default());
+        } else {
+            self.cookies.extend(parse_cookies_txt(&contents));
+        }
+
+        Ok(())
+    }
+
+    /// Initial state for the browser context, if there's anything to load
+    pub fn storage_state(&self) -> Option<StorageState> {
+        (!self.cookies.is_empty() || !self.origins.is_empty()).then(|| {
+            StorageState {
+                cookies: Some(self.cookies.clone()),
+                origins: Some(self.origins.clone()),
+            }
+        })
+    }
+
+    /// Extra headers to send when fetching `url`
+    ///
+    /// Later headers override earlier ones of the same name.
+    pub fn headers_for(&self, url: &Url) -> HashMap<String, String> {
+        let host = url.host_str().unwrap_or_default();
+        self.headers
+            .iter()
+            .filter(|h| h.applies_to(host))
+            .map(|h| (h.name.to_ascii_lowercase(), h.value.clone()))
+            .collect()
+    }
+
+    /// Which of the headers restricted to certain hosts apply to `url`, as
+    /// indices into `headers`
+    ///
+    /// Pages that differ in this need browser contexts of their own, as a
+    /// context sends the same headers with every request.
+    pub fn host_scope(&self, url: &Url) -> Vec<usize> {
+        let host = url.host_str().unwrap_or_default();
+        self.headers
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| h.hosts.is_some() && h.applies_to(host))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Whether any header restricted to certain hosts, other than a
+    /// `User-Agent`, applies to `url`
+    ///
+    /// Such headers may well be credentials, which the browser would send
+    /// along with requests to every other host the page loads from.
+    pub fn has_host_headers(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or_default();
+        self.headers.iter().any(|h| {
+            h.hosts.is_some()
+                && h.applies_to(host)
+                && !h.name.eq_ignore_ascii_case("User-Agent")
+        })
+    }
+
+    /// `Cookie` header for a request to `url`, if any cookies apply
+    ///
+    /// Only needed for requests made without the browser, which otherwise
+    /// keeps track of cookies itself.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let cookies: Vec<String> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie_applies(cookie, url))
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        (!cookies.is_empty()).then(|| cookies.join("; "))
+    }
+}
+
+/// Whether a browser would send `cookie` with a request to `url`
+fn cookie_applies(cookie: &Cookie, url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    let origin = cookie.url.as_deref().and_then(|u| Url::parse(u).ok());
+
+    let domain_matches = match (&cookie.domain, &origin) {
+        (Some(domain), _) => match domain.strip_prefix('.') {
+            Some(domain) => {
+                host == domain || host.ends_with(&format!(".{domain}"))
+            }
+            None => host == domain,
+        },
+        (None, Some(origin)) => origin.host_str() == Some(host),
+        (None, None) => false,
+    };
+
+    let path = cookie.path.as_deref().unwrap_or("/");
+    let path_matches = url.path().starts_with(path)
+        && (path.ends_with('/')
+            || url.path().len() == path.len()
+            || url.path()[path.len()..].starts_with('/'));
+
+    domain_matches
+        && path_matches
+        && (cookie.secure != Some(true) || url.scheme() == "https")
+}
+
+/// Cookies in the Netscape `cookies.txt` format
+///
+/// Each line holds seven tab-separated fields: domain, whether subdomains
+/// are included, path, whether the cookie is secure, expiry (zero for
+/// session cookies), name and value.
+fn parse_cookies_txt(contents: &str) -> Vec<Cookie> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.starts_with('#') || line.trim().is_empty() {
+                return None;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] =
+                fields[..]
+            else {
+                return None;
+            };
+
+            let domain = domain.trim_start_matches('.');
+            let domain = if subdomains.eq_ignore_ascii_case("true") {
+                format!(".{domain}")
+            } else {
+                domain.to_string()
+            };
+
+            Some(Cookie {
+                name: name.to_string(),
+                value: value.trim_end_matches('\r').to_string(),
+                url: None,
+                domain: Some(domain),
+                path: Some(path.to_string()),
+                expires: expires
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|expires| *expires > 0.0),
+                http_only: Some(http_only),
+                secure: Some(secure.eq_ignore_ascii_case("true")),
+                same_site: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cookies_txt() {
+        let txt = "# Netscape HTTP Cookie File\n\
+                   .example.com\tTRUE\t/\tTRUE\t1893456000\tsid\tabc123\n\
+                   #HttpOnly_wiki.corp.example\tFALSE\t/w\tFALSE\t0\ttoken\tx=y\n\
+                   malformed line\n";
+        let cookies = parse_cookies_txt(txt);
+
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies[0].domain.as_deref(), Some(".example.com"));
+        assert_eq!(cookies[0].expires, Some(1893456000.0));
+        assert_eq!(cookies[0].secure, Some(true));
+        assert_eq!(cookies[0].http_only, Some(false));
+
+        assert_eq!(cookies[1].name, "token");
+        assert_eq!(cookies[1].value, "x=y");
+        assert_eq!(cookies[1].domain.as_deref(), Some("wiki.corp.example"));
+        assert_eq!(cookies[1].path.as_deref(), Some("/w"));
+        assert_eq!(cookies[1].expires, None);
+        assert_eq!(cookies[1].http_only, Some(true));
+    }
+
+    #[test]
+    fn test_headers_for() {
+        let session = SessionConfig {
+            headers: vec![
+                "X-Requested-By: slurpsearch".parse().unwrap(),
+                "*.corp.example=Authorization: Basic dXNlcjpwYXNz=="
+                    .parse()
+                    .unwrap(),
+                HostHeader::parse_with_name(
+                    "User-Agent",
+                    "news.example=Mozilla/5.0 (X11; Linux x86_64)",
+                ),
+            ],
+            ..Default::default()
+        };
+        let headers = |s: &str| session.headers_for(&Url::parse(s).unwrap());
+
+        let wiki = headers("https://wiki.corp.example/page");
+        assert_eq!(wiki["authorization"], "Basic dXNlcjpwYXNz==");
+        assert_eq!(wiki["x-requested-by"], "slurpsearch");
+        assert!(!wiki.contains_key("user-agent"));
+
+        let news = headers("https://news.example/");
+        assert_eq!(news["user-agent"], "Mozilla/5.0 (X11; Linux x86_64)");
+        assert!(!news.contains_key("authorization"));
+
+        assert!("no colon here".parse::<HostHeader>().is_err());
+
+        assert_eq!(session.host_scope(&url("https://wiki.corp.example/")), [1]);
+        assert_eq!(session.host_scope(&url("https://news.example/")), [2]);
+        assert!(session.host_scope(&url("https://cdn.example/")).is_empty());
+        assert!(session.has_host_headers(&url("https://wiki.corp.example/")));
+        assert!(!session.has_host_headers(&url("https://news.example/")));
+        assert!(!session.has_host_headers(&url("https://cdn.example/")));
+    }
+
+    #[test]
+    fn test_cookie_header() {
+        let session = SessionConfig {
+            cookies: parse_cookies_txt(
+                ".example.com\tTRUE\t/\tTRUE\t0\tsid\tabc123\n\
+                 wiki.corp.example\tFALSE\t/w\tFALSE\t0\ttoken\tx=y\n",
+            ),
+            ..Default::default()
+        };
+        let cookie = |s: &str| session.cookie_header(&url(s));
+
+        assert_eq!(
+            cookie("https://www.example.com/a").as_deref(),
+            Some("sid=abc123")
+        );
+        assert_eq!(cookie("http://www.example.com/a"), None);
+        assert_eq!(cookie("https://example.com.evil/"), None);
+        assert_eq!(
+            cookie("http://wiki.corp.example/w/page").as_deref(),
+            Some("token=x=y")
+        );
+        assert_eq!(cookie("http://wiki.corp.example/wiki"), None);
+        assert_eq!(cookie("http://www.corp.example/w/page"), None);
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+}
@@ -0,0 +1,322 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use playwright::api::{Page, frame::FrameState};
+use tracing::debug;
+use url::Url;
+
+use crate::sites::HostPattern;
+
+/// Default number of times [`Settle::Scroll`] scrolls to the bottom
+pub const DEFAULT_SCROLLS: u32 = 5;
+
+/// Default upper bound on how long the waiting strategies may take
+pub const DEFAULT_SETTLE_CAP: Duration = Duration::from_secs(10);
+
+/// How long a page must stay quiet before it's considered settled
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+/// Pause after each scroll for lazily-loaded content to arrive
+const SCROLL_PAUSE: Duration = Duration::from_millis(750);
+
+/// Scrolls to the bottom up to `times` times, stopping early once the page
+/// stops growing
+const SCROLL_JS: &str = r#"async ([times, pause]) => {
+    const sleep = ms => new Promise(r => setTimeout(r, ms));
+    let height = -1;
+    for (let i = 0; i < times; i++) {
+        const h = document.documentElement.scrollHeight;
+        if (h === height) break;
+        height = h;
+        window.scrollTo(0, h);
+        await sleep(pause);
+    }
+    window.scrollTo(0, 0);
+    return true;
+}"#;
+
+/// Keeps count of the `fetch` and `XMLHttpRequest` calls in flight, and
+/// lifts the resource timing buffer's limit (250 entries in Chrome) so that
+/// busy pages don't stop recording loads
+///
+/// Must run before the page's own scripts, i.e. as an init script.
+pub const NETWORK_TRACKER_JS: &str = r#"(() => {
+    if (window.__slurpsearchInflight !== undefined) return;
+    window.__slurpsearchInflight = 0;
+    try { performance.setResourceTimingBufferSize(1e6); } catch (e) {}
+    const done = () => { window.__slurpsearchInflight--; };
+    const fetch = window.fetch;
+    if (fetch) {
+        window.fetch = function (...args) {
+            window.__slurpsearchInflight++;
+            return fetch.apply(this, args).finally(done);
+        };
+    }
+    const send = XMLHttpRequest.prototype.send;
+    XMLHttpRequest.prototype.send = function (...args) {
+        window.__slurpsearchInflight++;
+        this.addEventListener("loadend", done, { once: true });
+        return send.apply(this, args);
+    };
+})()"#;
+
+/// Waits until no request tracked by [`NETWORK_TRACKER_JS`] is in flight and
+/// no resource has finished loading for `quiet` milliseconds
+const NETWORK_IDLE_JS: &str = r#"async ([quiet, cap]) => {
+    const sleep = ms => new Promise(r => setTimeout(r, ms));
+    const start = performance.now();
+    let count = -1, since = start;
+    while (performance.now() - start < cap) {
+        const n = performance.getEntriesByType("resource").length;
+        if (n !== count || window.__slurpsearchInflight > 0) {
+            count = n;
+            since = performance.now();
+        } else if (performance.now() - since >= quiet) {
+            return true;
+        }
+        await sleep(100);
+    }
+    return false;
+}"#;
+
+/// Waits until the length of the page's text hasn't changed for `quiet`
+/// milliseconds
+const TEXT_STABLE_JS: &str = r#"async ([quiet, cap]) => {
+    const sleep = ms => new Promise(r => setTimeout(r, ms));
+    const start = performance.now();
+    let length = -1, since = start;
+    while (performance.now() - start < cap) {
+        const n = document.body ? document.body.innerText.length : 0;
+        if (n !== length) {
+            length = n;
+            since = performance.now();
+        } else if (performance.now() - since >= quiet) {
+            return true;
+        }
+        await sleep(100);
+    }
+    return false;
+}"#;
+
+/// What to wait for after navigation before reading a page's HTML
+///
+/// Written on the command line as `delay[:MS]`, `scroll[:TIMES]`,
+/// `idle[:SECS]`, `selector:CSS` or `stable[:SECS]`, where `SECS` caps how
+/// long to wait.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Settle {
+    /// Wait a fixed amount of time
+    Delay(Duration),
+    /// Scroll to the bottom repeatedly so infinite-scroll pages load more
+    Scroll(u32),
+    /// Wait until no requests are in flight and nothing has finished loading
+    /// for a short while
+    NetworkIdle(Duration),
+    /// Wait for an element matching a CSS selector to appear
+    Selector(String, Duration),
+    /// Wait until the amount of text on the page stops changing
+    TextStable(Duration),
+}
+
+impl Default for Settle {
+    fn default() -> Self {
+        Self::Delay(Duration::from_millis(250))
+    }
+}
+
+impl Settle {
+    /// Wait for `page` to settle. Failing to do so isn't an error: we just
+    /// read whatever the page has rendered so far.
+    pub async fn wait(&self, page: &Page) {
+        let res = match self {
+            Self::Delay(delay) => {
+                page.wait_for_timeout(delay.as_millis() as f64).await;
+                Ok(true)
+            }
+            Self::Scroll(times) => {
+                page.evaluate(
+                    SCROLL_JS,
+                    (*times, SCROLL_PAUSE.as_millis() as u64),
+                )
+                .await
+            }
+            Self::NetworkIdle(cap) => {
+                page.evaluate(
+                    NETWORK_IDLE_JS,
+                    (QUIET_PERIOD.as_millis() as u64, cap.as_millis() as u64),
+                )
+                .await
+            }
+            Self::Selector(selector, cap) => page
+                .wait_for_selector_builder(selector)
+                .state(FrameState::Attached)
+                .timeout(cap.as_millis() as f64)
+                .wait_for_selector()
+                .await
+                .map(|el| el.is_some()),
+            Self::TextStable(cap) => {
+                page.evaluate(
+                    TEXT_STABLE_JS,
+                    (QUIET_PERIOD.as_millis() as u64, cap.as_millis() as u64),
+                )
+                .await
+            }
+        };
+
+        match res {
+            Ok(true) => {}
+            Ok(false) => debug!("page didn't settle ({self}); continuing"),
+            Err(e) => debug!("settling ({self}) failed: {e:?}; continuing"),
+        }
+    }
+}
+
+impl Display for Settle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Delay(delay) => write!(f, "delay:{}", delay.as_millis()),
+            Self::Scroll(times) => write!(f, "scroll:{times}"),
+            Self::NetworkIdle(cap) => write!(f, "idle:{}", cap.as_secs()),
+            Self::Selector(selector, _) => write!(f, "selector:{selector}"),
+            Self::TextStable(cap) => write!(f, "stable:{}", cap.as_secs()),
+        }
+    }
+}
+
+impl FromStr for Settle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+
+        let number = |arg: Option<&str>, default: u64| match arg {
+            Some(arg) => arg
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid number `{arg}`")),
+            None => Ok(default),
+        };
+        let cap = number(arg, DEFAULT_SETTLE_CAP.as_secs());
+
+        match name.trim() {
+            "delay" => {
+                Ok(Self::Delay(Duration::from_millis(number(arg, 250)?)))
+            }
+            "scroll" => Ok(Self::Scroll(
+                number(arg, DEFAULT_SCROLLS.into())?
+                    .try_into()
+                    .map_err(|_| "too many scrolls".to_string())?,
+            )),
+            "idle" => Ok(Self::NetworkIdle(Duration::from_secs(cap?))),
+            "stable" => Ok(Self::TextStable(Duration::from_secs(cap?))),
+            "selector" => match arg.map(str::trim) {
+                Some(selector) if !selector.is_empty() => {
+                    Ok(Self::Selector(selector.to_string(), DEFAULT_SETTLE_CAP))
+                }
+                _ => Err("`selector` needs a CSS selector".to_string()),
+            },
+            other => Err(format!(
+                "unknown settle strategy `{other}` (expected delay, scroll, idle, selector or stable)"
+            )),
+        }
+    }
+}
+
+/// A settle strategy that only applies to certain hosts, written as
+/// `HOST=STRATEGY` (e.g., `*.substack.com=scroll:8`)
+#[derive(Clone, Debug)]
+pub struct HostSettle {
+    pub pattern: HostPattern,
+    pub settle: Settle,
+}
+
+impl FromStr for HostSettle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, settle) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected HOST=STRATEGY, got `{s}`"))?;
+
+        Ok(Self {
+            pattern: HostPattern::new(host.trim()),
+            settle: settle.parse()?,
+        })
+    }
+}
+
+/// Settle strategies to use for each page
+#[derive(Clone, Debug, Default)]
+pub struct SettleConfig {
+    /// Used for pages whose host matches none of `hosts`
+    pub default: Settle,
+    /// Per-host overrides, the first match of which wins
+    pub hosts: Vec<HostSettle>,
+}
+
+impl SettleConfig {
+    pub fn for_url(&self, url: &Url) -> &Settle {
+        url.host_str()
+            .and_then(|host| {
+                self.hosts.iter().find(|h| h.pattern.matches(host))
+            })
+            .map_or(&self.default, |h| &h.settle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("scroll".parse(), Ok(Settle::Scroll(DEFAULT_SCROLLS)));
+        assert_eq!("scroll:3".parse(), Ok(Settle::Scroll(3)));
+        assert_eq!(
+            "delay:1000".parse(),
+            Ok(Settle::Delay(Duration::from_secs(1)))
+        );
+        assert_eq!("idle".parse(), Ok(Settle::NetworkIdle(DEFAULT_SETTLE_CAP)));
+        assert_eq!(
+            "stable:4".parse(),
+            Ok(Settle::TextStable(Duration::from_secs(4)))
+        );
+        assert_eq!(
+            "selector:div.post:not(.ad)".parse(),
+            Ok(Settle::Selector(
+                "div.post:not(.ad)".to_string(),
+                DEFAULT_SETTLE_CAP
+            ))
+        );
+        assert!("selector".parse::<Settle>().is_err());
+        assert!("scroll:lots".parse::<Settle>().is_err());
+        assert!("nap".parse::<Settle>().is_err());
+    }
+
+    #[test]
+    fn test_for_url() {
+        let config = SettleConfig {
+            default: Settle::default(),
+            hosts: vec![
+                "*.substack.com=scroll:8".parse().unwrap(),
+                "medium.com=stable".parse().unwrap(),
+            ],
+        };
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert_eq!(
+            config.for_url(&url("https://foo.substack.com/p/bar")),
+            &Settle::Scroll(8)
+        );
+        assert_eq!(
+            config.for_url(&url("https://medium.com/@a/b")),
+            &Settle::TextStable(DEFAULT_SETTLE_CAP)
+        );
+        assert_eq!(
+            config.for_url(&url("https://example.com/")),
+            &Settle::default()
+        );
+    }
+}
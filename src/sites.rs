@@ -0,0 +1,398 @@
+use eyre::Result;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    extract::{DocBuilder, element_text, extract_text, normalize_text},
+    rag::{ContentKind, WebDoc},
+};
+
+/// Extracts text from pages whose layout we know ahead of time
+pub trait SiteExtractor: Send + Sync {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Read the interesting parts of `document` into `docs`
+    fn extract(&self, document: &Html, docs: &mut DocBuilder);
+}
+
+/// Matches hostnames either exactly or, if prefixed with `*.`, by domain
+///
+/// `*.reddit.com` matches `reddit.com` as well as any of its subdomains (e.g.,
+/// `old.reddit.com`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostPattern(String);
+
+impl HostPattern {
+    pub fn new(pattern: &str) -> Self {
+        Self(pattern.to_ascii_lowercase())
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        match self.0.strip_prefix("*.") {
+            Some(domain) => {
+                host == domain
+                    || host
+                        .strip_suffix(domain)
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            None => host == self.0,
+        }
+    }
+}
+
+/// Chooses the extractor to use for a page based on its host
+///
+/// Extractors are consulted in registration order; pages that match none of
+/// them (or for which the matching extractor finds nothing) are handled by
+/// [`extract_text`].
+pub struct ExtractorRegistry {
+    extractors: Vec<(HostPattern, Box<dyn SiteExtractor>)>,
+}
+
+impl Default for ExtractorRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(HostPattern::new("github.com"), GitHub);
+        registry.register(HostPattern::new("news.ycombinator.com"), HackerNews);
+        registry.register(HostPattern::new("*.reddit.com"), Reddit);
+        registry.register(HostPattern::new("*.wikipedia.org"), Wikipedia);
+        registry.register(HostPattern::new("arxiv.org"), Arxiv);
+        registry
+    }
+}
+
+impl ExtractorRegistry {
+    /// A registry with no site-specific extractors at all
+    pub fn empty() -> Self {
+        Self {
+            extractors: Vec::new(),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        pattern: HostPattern,
+        extractor: impl SiteExtractor + 'static,
+    ) {
+        self.extractors.push((pattern, Box::new(extractor)));
+    }
+
+    /// Extractor responsible for `url`, if any
+    pub fn find(&self, url: &Url) -> Option<&dyn SiteExtractor> {
+        let host = url.host_str()?;
+        self.extractors
+            .iter()
+            .find(|(pattern, _)| pattern.matches(host))
+            .map(|(_, extractor)| extractor.as_ref())
+    }
+
+    /// Extract the text of a page, preferring a site-specific extractor
+    pub fn extract(&self, url: Url, html: &str) -> Result<Vec<WebDoc>> {
+        if let Some(extractor) = self.find(&url) {
+            let document = Html::parse_document(html);
+            let mut docs = DocBuilder::new(url.clone());
+            extractor.extract(&document, &mut docs);
+            let docs = docs.finish();
+
+            if !docs.is_empty() {
+                return Ok(docs);
+            }
+
+            debug!(
+                "{} extractor found nothing in {url}; falling back to generic extraction",
+                extractor.name()
+            );
+        }
+
+        extract_text(url, html)
+    }
+}
+
+fn select<'a>(
+    document: &'a Html,
+    selector: &str,
+) -> impl Iterator<Item = ElementRef<'a>> {
+    /* selectors are all literals below, so failing to parse is a bug */
+    let selector = Selector::parse(selector).unwrap();
+    document.select(&selector).collect::<Vec<_>>().into_iter()
+}
+
+/// Repositories (README), issues and pull requests
+pub struct GitHub;
+
+impl SiteExtractor for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn extract(&self, document: &Html, docs: &mut DocBuilder) {
+        for title in select(
+            document,
+            "bdi.js-issue-title, [data-testid=\"issue-title\"]",
+        ) {
+            docs.push_element(title);
+        }
+
+        for about in select(document, ".BorderGrid-cell > p.f4") {
+            docs.push_element(about);
+        }
+
+        // READMEs, issue bodies and comments are all rendered Markdown.
+        for body in select(document, ".markdown-body") {
+            docs.push_blocks_or_element(body);
+        }
+    }
+}
+
+/// Story pages, including their (table-based) comment threads
+pub struct HackerNews;
+
+impl SiteExtractor for HackerNews {
+    fn name(&self) -> &'static str {
+        "Hacker News"
+    }
+
+    fn extract(&self, document: &Html, docs: &mut DocBuilder) {
+        for title in select(document, ".titleline > a") {
+            docs.push_element(title);
+        }
+
+        // Comment text is loose inline text followed by <p>s, so take it whole.
+        for text in select(document, ".toptext, .commtext") {
+            docs.push_element(text);
+        }
+    }
+}
+
+/// Posts and comments on both old and new Reddit
+pub struct Reddit;
+
+impl SiteExtractor for Reddit {
+    fn name(&self) -> &'static str {
+        "Reddit"
+    }
+
+    fn extract(&self, document: &Html, docs: &mut DocBuilder) {
+        for title in select(
+            document,
+            "shreddit-post [slot=\"title\"], #siteTable a.title",
+        ) {
+            docs.push_element(title);
+        }
+
+        for body in select(
+            document,
+            "shreddit-post [slot=\"text-body\"], \
+             shreddit-comment [slot=\"comment\"], \
+             .thing .usertext-body .md",
+        ) {
+            docs.push_blocks_or_element(body);
+        }
+    }
+}
+
+/// Articles, minus navigation boxes, edit links and citation markers
+pub struct Wikipedia;
+
+impl SiteExtractor for Wikipedia {
+    fn name(&self) -> &'static str {
+        "Wikipedia"
+    }
+
+    fn extract(&self, document: &Html, docs: &mut DocBuilder) {
+        for title in select(document, "#firstHeading") {
+            docs.push_element(title);
+        }
+
+        let mut article = DocBuilder::new(docs.url().clone());
+        for body in select(document, "#mw-content-text .mw-parser-output") {
+            article.push_blocks(body);
+        }
+
+        let noise =
+            Regex::new(r"\[(\d+|[a-z]|edit|citation needed)\]").unwrap();
+        for doc in article.finish() {
+            let text = normalize_text(std::iter::once(
+                &*noise.replace_all(&doc.text, ""),
+            ));
+            docs.push(text, doc.kind, doc.anchor);
+        }
+    }
+}
+
+/// Abstract pages (`/abs/...`)
+pub struct Arxiv;
+
+impl SiteExtractor for Arxiv {
+    fn name(&self) -> &'static str {
+        "arXiv"
+    }
+
+    fn extract(&self, document: &Html, docs: &mut DocBuilder) {
+        // Each field is preceded by a descriptor such as "Abstract:".
+        let descriptor = Regex::new(r"^(Title|Authors|Abstract):\s*").unwrap();
+
+        for el in select(
+            document,
+            "h1.title, .authors, blockquote.abstract, td.subjects",
+        ) {
+            let text = element_text(el);
+            let text = descriptor.replace(&text, "").into_owned();
+            docs.push(text, ContentKind::Prose, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(url: &str, html: &str) -> Vec<String> {
+        ExtractorRegistry::default()
+            .extract(Url::parse(url).unwrap(), html)
+            .unwrap()
+            .into_iter()
+            .map(|doc| doc.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_host_pattern() {
+        let exact = HostPattern::new("arxiv.org");
+        assert!(exact.matches("arxiv.org"));
+        assert!(exact.matches("ARXIV.org"));
+        assert!(!exact.matches("export.arxiv.org"));
+
+        let wildcard = HostPattern::new("*.reddit.com");
+        assert!(wildcard.matches("reddit.com"));
+        assert!(wildcard.matches("old.reddit.com"));
+        assert!(!wildcard.matches("notreddit.com"));
+    }
+
+    #[test]
+    fn test_unknown_host_uses_generic_extraction() {
+        let registry = ExtractorRegistry::default();
+        let url = Url::parse("https://example.com/post").unwrap();
+        assert!(registry.find(&url).is_none());
+
+        let html = "<html><body><p>Hello</p></body></html>";
+        assert_eq!(
+            registry.extract(url.clone(), html).unwrap(),
+            extract_text(url, html).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_github() {
+        let texts = extract(
+            "https://github.com/jmcph4/slurpsearch",
+            include_str!("../tests/fixtures/sites/github.html"),
+        );
+        assert_eq!(
+            texts,
+            vec![
+                "Search the contents of the pages linked from your bookmarks",
+                "slurpsearch",
+                "Given a file containing URLs, extract each one and search the resource located there for the provided search term.",
+                "Usage",
+                "$ slurpsearch bookmarks.md \"memory allocators\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hacker_news() {
+        let texts = extract(
+            "https://news.ycombinator.com/item?id=41000000",
+            include_str!("../tests/fixtures/sites/hackernews.html"),
+        );
+        assert_eq!(
+            texts,
+            vec![
+                "Show HN: A search engine for your bookmarks",
+                "I kept losing articles I had bookmarked, so I wrote a tool to search them.",
+                "Does it handle pages that need JavaScript to render? Most of my bookmarks are SPAs.",
+                "Yes, it renders everything in headless Chromium before extracting text.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hacker_news_comment_anchors() {
+        let docs = ExtractorRegistry::default()
+            .extract(
+                Url::parse("https://news.ycombinator.com/item?id=41000000")
+                    .unwrap(),
+                include_str!("../tests/fixtures/sites/hackernews.html"),
+            )
+            .unwrap();
+        assert_eq!(docs[2].anchor.as_deref(), Some("41000001"));
+        assert_eq!(docs[3].anchor.as_deref(), Some("41000002"));
+    }
+
+    #[test]
+    fn test_reddit() {
+        let texts = extract(
+            "https://old.reddit.com/r/productivity/comments/abc123/",
+            include_str!("../tests/fixtures/sites/reddit.html"),
+        );
+        assert_eq!(
+            texts,
+            vec![
+                "What's your bookmark workflow?",
+                "I save hundreds of links a month and never find them again.",
+                "I dump everything into a plain text file with timestamps.",
+                "Grep does the rest.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wikipedia() {
+        let docs = ExtractorRegistry::default()
+            .extract(
+                Url::parse(
+                    "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                )
+                .unwrap(),
+                include_str!("../tests/fixtures/sites/wikipedia.html"),
+            )
+            .unwrap();
+        let texts: Vec<&str> = docs.iter().map(|d| d.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Rust (programming language)",
+                "Rust",
+                "Paradigms: Concurrent, functional, generic, imperative",
+                "Designed by: Graydon Hoare",
+                "Rust is a general-purpose programming language emphasizing performance, type safety, and concurrency.",
+                "History",
+                "Rust began as a personal project by Mozilla employee Graydon Hoare in 2006.",
+            ]
+        );
+        assert_eq!(docs[2].kind, ContentKind::Tabular);
+        assert_eq!(docs[6].anchor.as_deref(), Some("History"));
+    }
+
+    #[test]
+    fn test_arxiv() {
+        let texts = extract(
+            "https://arxiv.org/abs/1706.03762",
+            include_str!("../tests/fixtures/sites/arxiv.html"),
+        );
+        assert_eq!(
+            texts,
+            vec![
+                "Attention Is All You Need",
+                "Ashish Vaswani, Noam Shazeer",
+                "The dominant sequence transduction models are based on complex recurrent or convolutional neural networks. We propose a new simple network architecture, the Transformer, based solely on attention mechanisms.",
+                "Computation and Language (cs.CL); Machine Learning (cs.LG)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use regex::Regex;
+use url::Url;
+
+/// Schemes of the URLs we recognise when written out in full
+const SCHEMES: &[&str] = &["https://", "http://", "file://"];
+
+/// Top-level domains a bare scheme-less domain (i.e., one without a path or
+/// `www.` prefix) must end in to be recognised
+///
+/// Deliberately omits TLDs that double as common file extensions (`.md`,
+/// `.rs`, `.py`, `.sh`, `.pl`, etc.), so that file names in notes aren't
+/// mistaken for websites.
+const KNOWN_TLDS: &[&str] = &[
+    "ai", "app", "at", "au", "be", "biz", "blog", "br", "ca", "ch", "cn", "co",
+    "com", "cz", "de", "dev", "dk", "edu", "es", "eu", "fi", "fr", "gg", "gov",
+    "ie", "in", "info", "io", "it", "jp", "kr", "me", "mil", "net", "news",
+    "nl", "no", "nz", "org", "page", "ru", "se", "site", "tech", "tv", "uk",
+    "us", "wiki", "xyz", "za",
+];
+
+/// Host (and optional port) of an address written without a scheme
+const DOMAIN_REGEX: &str =
+    r"(?i)^(?:[a-z0-9](?:[a-z0-9-]*[a-z0-9])?\.)+([a-z]{2,})(?::\d+)?";
+
+/// Punctuation that ends a sentence rather than a bare URL
+const TRAILING_PUNCTUATION: &[char] =
+    &['.', ',', ';', ':', '!', '?', '\'', '"', '*', '_', '~'];
+
+/// Finds URLs in free text, Markdown and HTML
+///
+/// Recognises:
+///
+///  - Markdown inline links, `[text](url "title")`, including destinations
+///    wrapped in `<>` and those containing balanced parentheses
+///  - Markdown autolinks, `<url>`
+///  - HTML `href` attributes, quoted or not
+///  - bare `http://`, `https://` and `file://` URLs, which may contain
+///    balanced parentheses and brackets but not trailing punctuation
+///  - addresses without a scheme, such as `www.example.com` or
+///    `example.com/post`, which are assumed to be HTTPS
+pub struct UrlTokenizer {
+    domain: Regex,
+}
+
+impl Default for UrlTokenizer {
+    fn default() -> Self {
+        Self {
+            domain: Regex::new(DOMAIN_REGEX).unwrap(),
+        }
+    }
+}
+
+impl UrlTokenizer {
+    /// Every URL in `text` along with the byte offset it starts at, in order
+    /// of appearance
+    pub fn tokenize(&self, text: &str) -> Vec<(usize, Url)> {
+        let mut out = Vec::new();
+        let mut i = 0;
+
+        while let Some(c) = text[i..].chars().next() {
+            if let Some((start, end, url)) = self.token_at(text, i) {
+                out.extend(url.map(|url| (start, url)));
+                i = end.max(i + c.len_utf8());
+            } else {
+                i += c.len_utf8();
+            }
+        }
+
+        out
+    }
+
+    /// The token starting at byte `i`, if any, as its start, end and URL
+    ///
+    /// Tokens that turn out not to contain a URL (e.g., relative Markdown
+    /// links) are still returned so that their contents are skipped.
+    fn token_at(
+        &self,
+        text: &str,
+        i: usize,
+    ) -> Option<(usize, usize, Option<Url>)> {
+        let rest = &text[i..];
+
+        if rest.starts_with("](") {
+            let (start, end, dest) = markdown_destination(text, i + 2);
+            return Some((start, end, self.parse(dest)));
+        }
+
+        if let Some(inner) = rest.strip_prefix('<') {
+            let close =
+                inner.find(|c: char| c == '>' || c == '<' || c.is_whitespace());
+            if let Some(close) = close
+                && inner[close..].starts_with('>')
+                && let Some(url) = self.parse(&inner[..close])
+            {
+                return Some((i + 1, i + 1 + close + 1, Some(url)));
+            }
+            return None;
+        }
+
+        if !at_word_start(text, i) {
+            return None;
+        }
+
+        if starts_with_ignore_case(rest, "href") {
+            return href_value(text, i + 4).map(|(start, end, value)| {
+                (start, end, self.parse(&decode_entities(value)))
+            });
+        }
+
+        if SCHEMES
+            .iter()
+            .any(|scheme| starts_with_ignore_case(rest, scheme))
+        {
+            let end = bare_url_end(text, i);
+            return Some((i, end, self.parse(&text[i..end])));
+        }
+
+        let domain = self.domain.find(rest)?;
+        let after = i + domain.end();
+        let end = if text[after..].starts_with('/') {
+            bare_url_end(text, after)
+        } else {
+            after
+        };
+
+        /* `user@example.com` is an email address, not a website */
+        if text[end..].starts_with('@') {
+            return None;
+        }
+
+        Some((i, end, self.parse(&text[i..end])))
+    }
+
+    /// Interpret a candidate as an absolute URL, if it is one
+    fn parse(&self, candidate: &str) -> Option<Url> {
+        let candidate = candidate.trim();
+
+        if SCHEMES
+            .iter()
+            .any(|scheme| starts_with_ignore_case(candidate, scheme))
+        {
+            return Url::parse(candidate).ok();
+        }
+
+        let caps = self.domain.captures(candidate)?;
+        let domain = caps.get(0)?;
+        let rest = &candidate[domain.end()..];
+        if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+            return None;
+        }
+
+        /* bare domains need a TLD we know; anything more is clearly a URL */
+        let bare =
+            rest.is_empty() && !starts_with_ignore_case(candidate, "www.");
+        let tld = caps[1].to_ascii_lowercase();
+        if bare && !KNOWN_TLDS.contains(&tld.as_str()) {
+            return None;
+        }
+
+        Url::parse(&format!("https://{candidate}")).ok()
+    }
+}
+
+fn starts_with_ignore_case(s: &str, prefix: &str) -> bool {
+    s.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// Whether byte `i` begins a word, i.e. isn't in the middle of another
+/// token, path or email address
+fn at_word_start(text: &str, i: usize) -> bool {
+    text[..i].chars().next_back().is_none_or(|c| {
+        !(c.is_alphanumeric()
+            || matches!(c, '@' | '/' | '.' | '-' | '_' | ':' | '%'))
+    })
+}
+
+/// Whether `c` may appear in a bare URL at all
+fn is_url_char(c: char) -> bool {
+    !c.is_whitespace()
+        && !c.is_control()
+        && !matches!(c, '<' | '>' | '"' | '`' | '{' | '}' | '|' | '\\' | '^')
+}
+
+/// End of a bare URL starting at byte `start`
+///
+/// Parentheses and brackets are included only if balanced, so URLs like
+/// `https://en.wikipedia.org/wiki/Rust_(programming_language)` survive
+/// intact while `(see https://example.com)` loses its closing parenthesis.
+/// Trailing punctuation is dropped.
+fn bare_url_end(text: &str, start: usize) -> usize {
+    let mut parens = 0usize;
+    let mut brackets = 0usize;
+    let mut end = start;
+
+    for (offset, c) in text[start..].char_indices() {
+        if !is_url_char(c) {
+            break;
+        }
+        match c {
+            '(' => parens += 1,
+            '[' => brackets += 1,
+            ')' if parens == 0 => break,
+            ']' if brackets == 0 => break,
+            ')' => parens -= 1,
+            ']' => brackets -= 1,
+            _ => {}
+        }
+        end = start + offset + c.len_utf8();
+    }
+
+    start
+        + text[start..end]
+            .trim_end_matches(TRAILING_PUNCTUATION)
+            .len()
+}
+
+/// Destination of a Markdown inline link whose `(` ends just before byte
+/// `open`, as its start, the end of the whole link and the destination
+fn markdown_destination(text: &str, open: usize) -> (usize, usize, &str) {
+    let leading = text[open..].len() - text[open..].trim_start().len();
+    let start = open + leading;
+    let rest = &text[start..];
+
+    let (dest_start, dest_end) = if let Some(inner) = rest.strip_prefix('<') {
+        match inner.find(['>', '\n']) {
+            Some(close) if inner[close..].starts_with('>') => {
+                (start + 1, start + 1 + close)
+            }
+            _ => (start, start),
+        }
+    } else {
+        let mut depth = 0usize;
+        let mut end = start;
+        for (offset, c) in rest.char_indices() {
+            match c {
+                c if c.is_whitespace() || c.is_control() => break,
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            end = start + offset + c.len_utf8();
+        }
+        (start, end)
+    };
+
+    /* skip any title so its contents aren't scanned for URLs */
+    let close = text[dest_end..]
+        .find(')')
+        .map_or(text.len(), |close| dest_end + close + 1);
+    let end = if text[dest_end..close].contains('\n') {
+        dest_end
+    } else {
+        close
+    };
+
+    (dest_start, end, &text[dest_start..dest_end])
+}
+
+/// Value of an `href` attribute whose name ends just before byte `i`, as its
+/// start, the end of the attribute and the value
+fn href_value(text: &str, i: usize) -> Option<(usize, usize, &str)> {
+    let rest = &text[i..];
+    let after_name = rest.trim_start();
+    let after_eq = after_name.strip_prefix('=')?.trim_start();
+    let start = i + (rest.len() - after_eq.len());
+
+    match after_eq.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let close = after_eq[1..].find(quote)?;
+            Some((start + 1, start + 1 + close + 1, &after_eq[1..1 + close]))
+        }
+        _ => {
+            let len = after_eq
+                .find(|c: char| c.is_whitespace() || c == '>')
+                .unwrap_or(after_eq.len());
+            Some((start, start + len, &after_eq[..len]))
+        }
+    }
+}
+
+/// Decode the entities that commonly appear in attribute values
+fn decode_entities(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let cases: &[(&str, &[&str])] = &[
+            /* bare URLs */
+            ("https://example.com", &["https://example.com/"]),
+            ("see https://example.com/a.", &["https://example.com/a"]),
+            (
+                "https://example.com/a?b=c, and more",
+                &["https://example.com/a?b=c"],
+            ),
+            ("HTTPS://EXAMPLE.COM/A", &["https://example.com/A"]),
+            ("\"https://example.com/q\"", &["https://example.com/q"]),
+            ("'https://example.com/q'", &["https://example.com/q"]),
+            ("**https://example.com/b**", &["https://example.com/b"]),
+            (
+                "https://a.example/x https://b.example/y",
+                &["https://a.example/x", "https://b.example/y"],
+            ),
+            ("http://localhost:8080/a", &["http://localhost:8080/a"]),
+            ("file:///home/me/a.html", &["file:///home/me/a.html"]),
+            (
+                "https://例え.jp/パス",
+                &["https://xn--r8jz45g.jp/%E3%83%91%E3%82%B9"],
+            ),
+            /* balanced parentheses and brackets */
+            (
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                &["https://en.wikipedia.org/wiki/Rust_(programming_language)"],
+            ),
+            (
+                "(see https://en.wikipedia.org/wiki/Rust_(programming_language))",
+                &["https://en.wikipedia.org/wiki/Rust_(programming_language)"],
+            ),
+            ("(https://example.com/a)", &["https://example.com/a"]),
+            ("(https://example.com/a).", &["https://example.com/a"]),
+            ("[https://example.com/a]", &["https://example.com/a"]),
+            (
+                "https://example.com/a[0]=1",
+                &["https://example.com/a[0]=1"],
+            ),
+            /* Markdown inline links */
+            ("[t](https://a.com/x)", &["https://a.com/x"]),
+            ("[t](https://a.com/x) (an aside)", &["https://a.com/x"]),
+            ("[t](https://a.com/x)(another)", &["https://a.com/x"]),
+            (
+                "[Rust](https://en.wikipedia.org/wiki/Rust_(programming_language))",
+                &["https://en.wikipedia.org/wiki/Rust_(programming_language)"],
+            ),
+            (
+                "[t](https://a.com/x \"Title with https://b.com\")",
+                &["https://a.com/x"],
+            ),
+            (
+                "[t](<https://a.com/with space>)",
+                &["https://a.com/with%20space"],
+            ),
+            ("[t]( https://a.com/x )", &["https://a.com/x"]),
+            ("[t](/relative/path)", &[]),
+            ("[t](#anchor)", &[]),
+            ("[t](example.com/post)", &["https://example.com/post"]),
+            (
+                "[a](https://a.com), [b](https://b.com).",
+                &["https://a.com/", "https://b.com/"],
+            ),
+            ("![img](https://a.com/i.png)", &["https://a.com/i.png"]),
+            (
+                "[https://a.com](https://b.com)",
+                &["https://a.com/", "https://b.com/"],
+            ),
+            /* autolinks */
+            ("<https://a.com/x>", &["https://a.com/x"]),
+            ("<https://a.com/x>.", &["https://a.com/x"]),
+            ("<www.example.com>", &["https://www.example.com/"]),
+            ("<not a link>", &[]),
+            ("<me@example.com>", &[]),
+            /* HTML */
+            (r#"<a href="https://a.com/x">t</a>"#, &["https://a.com/x"]),
+            (r#"<a HREF='https://a.com/x'>t</a>"#, &["https://a.com/x"]),
+            ("<a href=https://a.com/x>t</a>", &["https://a.com/x"]),
+            (
+                r#"<a href="https://a.com/?a=1&amp;b=2">t</a>"#,
+                &["https://a.com/?a=1&b=2"],
+            ),
+            (r#"<a href="/relative">t</a>"#, &[]),
+            (
+                r#"<a class="x" href = "https://a.com/(y)">https://a.com/(y)</a>"#,
+                &["https://a.com/(y)", "https://a.com/(y)"],
+            ),
+            /* addresses without a scheme */
+            ("www.example.com", &["https://www.example.com/"]),
+            ("example.org/post?id=1", &["https://example.org/post?id=1"]),
+            ("visit example.com.", &["https://example.com/"]),
+            ("docs.rs/tokio", &["https://docs.rs/tokio"]),
+            ("(example.com/a_(b))", &["https://example.com/a_(b)"]),
+            /* things that aren't URLs */
+            ("README.md src/main.rs", &[]),
+            ("me@example.com", &[]),
+            ("first.last@example.com", &[]),
+            ("v1.2.3 e.g. i.e. 3.14", &[]),
+            ("Node.js", &[]),
+            ("ftp://example.com/file", &[]),
+            ("[2025-12-12T17:18:12+10:00]", &[]),
+        ];
+
+        let tokenizer = UrlTokenizer::default();
+        for (text, expected) in cases {
+            let urls: Vec<String> = tokenizer
+                .tokenize(text)
+                .into_iter()
+                .map(|(_, url)| url.to_string())
+                .collect();
+            assert_eq!(urls, *expected, "tokenizing {text:?}");
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        let text = "[2025-12-12] [t](https://a.com) then https://b.com";
+        let offsets: Vec<usize> = UrlTokenizer::default()
+            .tokenize(text)
+            .into_iter()
+            .map(|(start, _)| start)
+            .collect();
+        assert_eq!(offsets, vec![17, 37]);
+    }
+}
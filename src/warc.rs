@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use eyre::{WrapErr, bail};
+use flate2::{
+    Compression,
+    read::{GzDecoder, MultiGzDecoder, ZlibDecoder},
+    write::GzEncoder,
+};
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    dates::{Timestamp, parse_timestamp},
+    http::USER_AGENT,
+};
+
+/// Version of the format we write
+const WARC_VERSION: &str = "WARC/1.1";
+
+/// Magic bytes at the start of a gzip member
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Page read from a WARC file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WarcPage {
+    pub url: Url,
+    /// When the page was captured
+    pub date: Option<Timestamp>,
+    pub html: String,
+}
+
+/// Whether `path` looks like a WARC file, going by its name
+pub fn is_warc(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".warc") || name.ends_with(".warc.gz")
+}
+
+/// A single record: its named fields (keyed in lowercase) and content block
+#[derive(Debug)]
+struct Record {
+    fields: HashMap<String, String>,
+    block: Vec<u8>,
+}
+
+impl Record {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(|value| value.as_str())
+    }
+}
+
+/// Read header lines up to and including the blank line that ends them,
+/// joining continuation lines
+fn read_fields<R: BufRead>(
+    reader: &mut R,
+) -> eyre::Result<HashMap<String, String>> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut last: Option<String> = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("unexpected end of file in record header");
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(fields);
+        }
+
+        if line.starts_with([' ', '\t'])
+            && let Some(value) =
+                last.as_ref().and_then(|name| fields.get_mut(name))
+        {
+            value.push(' ');
+            value.push_str(line.trim());
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("malformed header line `{line}`");
+        };
+        let name = name.trim().to_ascii_lowercase();
+        fields.insert(name.clone(), value.trim().to_string());
+        last = Some(name);
+    }
+}
+
+/// Next record in the file, or `None` at the end
+fn read_record<R: BufRead>(reader: &mut R) -> eyre::Result<Option<Record>> {
+    /* records are separated by blank lines */
+    let version = loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if !line.is_empty() {
+            break line.to_string();
+        }
+    };
+    if !version.starts_with("WARC/") {
+        bail!("expected a WARC record but found `{version}`");
+    }
+
+    let fields = read_fields(reader)?;
+    let length: usize = fields
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .ok_or_else(|| eyre::eyre!("record has no valid Content-Length"))?;
+    let mut block = vec![0; length];
+    reader
+        .read_exact(&mut block)
+        .wrap_err("record is shorter than its Content-Length")?;
+
+    Ok(Some(Record { fields, block }))
+}
+
+/// Undo `Transfer-Encoding: chunked`
+fn dechunk(mut body: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    loop {
+        let mut size = String::new();
+        body.read_line(&mut size)?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .wrap_err_with(|| format!("invalid chunk size `{size}`"))?;
+        if size == 0 || size > body.len() {
+            out.extend_from_slice(&body[..size.min(body.len())]);
+            return Ok(out);
+        }
+
+        out.extend_from_slice(&body[..size]);
+        body = &body[size..];
+        let mut crlf = String::new();
+        body.read_line(&mut crlf)?;
+    }
+}
+
+/// Whether a `Content-Type` (or its absence, for a body starting with
+/// `sniff`) indicates HTML
+fn is_html(content_type: Option<&str>, sniff: &[u8]) -> bool {
+    match content_type {
+        Some(content_type) => {
+            let mime = content_type.split(';').next().unwrap_or_default();
+            let mime = mime.trim().to_ascii_lowercase();
+            mime == "text/html" || mime == "application/xhtml+xml"
+        }
+        None => sniff.trim_ascii_start().starts_with(b"<"),
+    }
+}
+
+/// HTML sent in a `response` record's HTTP message, if it was a successful
+/// response with an HTML body
+fn html_from_response(block: &[u8]) -> eyre::Result<Option<String>> {
+    let mut reader = block;
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if !code.starts_with('2') {
+        debug!("skipping response with status `{}`", status.trim());
+        return Ok(None);
+    }
+
+    let headers = read_fields(&mut reader)?;
+    let mut body = reader.to_vec();
+    if headers
+        .get("transfer-encoding")
+        .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+    {
+        body = dechunk(&body)?;
+    }
+    if !is_html(headers.get("content-type").map(|s| s.as_str()), &body) {
+        return Ok(None);
+    }
+
+    let encoding = headers
+        .get("content-encoding")
+        .map(|encoding| encoding.trim().to_ascii_lowercase());
+    let body = match encoding.as_deref() {
+        None | Some("identity") => body,
+        Some("gzip" | "x-gzip") => {
+            let mut out = Vec::new();
+            GzDecoder::new(&body[..]).read_to_end(&mut out)?;
+            out
+        }
+        Some("deflate") => {
+            let mut out = Vec::new();
+            ZlibDecoder::new(&body[..]).read_to_end(&mut out)?;
+            out
+        }
+        Some(other) => {
+            debug!("skipping response with unsupported encoding `{other}`");
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Every successfully captured HTML page in a WARC stream, which may be
+/// gzipped (as is usual for `.warc.gz` files)
+pub fn parse_pages<R: Read>(reader: R) -> eyre::Result<Vec<WarcPage>> {
+    let mut reader = BufReader::new(reader);
+    let gzipped = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let mut reader: Box<dyn BufRead> = if gzipped {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+
+    let mut pages = Vec::new();
+    while let Some(record) = read_record(&mut reader)? {
+        let kind = record.field("warc-type").unwrap_or_default();
+        let html = match kind {
+            "response" => html_from_response(&record.block)
+                .inspect_err(|e| warn!("skipping malformed response: {e}"))
+                .ok()
+                .flatten(),
+            "resource"
+                if is_html(record.field("content-type"), &record.block) =>
+            {
+                Some(String::from_utf8_lossy(&record.block).into_owned())
+            }
+            _ => None,
+        };
+        let Some(html) = html else {
+            continue;
+        };
+
+        /* WARC 1.0 puts angle brackets around the URI */
+        let target = record.field("warc-target-uri").unwrap_or_default();
+        let target = target.trim_start_matches('<').trim_end_matches('>');
+        let Ok(url) = Url::parse(target) else {
+            debug!("skipping record with invalid target `{target}`");
+            continue;
+        };
+
+        pages.push(WarcPage {
+            url,
+            date: record.field("warc-date").and_then(parse_timestamp),
+            html,
+        });
+    }
+
+    Ok(pages)
+}
+
+/// Every successfully captured HTML page in the WARC file at `path`
+pub fn read_pages(path: &Path) -> eyre::Result<Vec<WarcPage>> {
+    let file = File::open(path)
+        .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    parse_pages(file)
+        .wrap_err_with(|| format!("failed to read WARC {}", path.display()))
+}
+
+/// Writes pages as `resource` records
+///
+/// We only have the rendered HTML rather than the HTTP exchange, so
+/// `response` records would have to make up a status and headers.
+pub struct WarcWriter<W: Write> {
+    out: W,
+    /// Compress each record as its own gzip member, as `.warc.gz` readers
+    /// expect
+    gzip: bool,
+}
+
+impl WarcWriter<BufWriter<File>> {
+    /// Start a WARC file at `path`, gzipped if its name ends in `.gz`
+    pub fn create(path: &Path) -> eyre::Result<Self> {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+        let gzip = path.extension().is_some_and(|ext| ext == "gz");
+        let mut writer = Self::new(BufWriter::new(file), gzip);
+        writer.write_info()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> WarcWriter<W> {
+    pub fn new(out: W, gzip: bool) -> Self {
+        Self { out, gzip }
+    }
+
+    /// Write a record whose content was captured at `date`
+    fn write_record(
+        &mut self,
+        date: Timestamp,
+        fields: &[(&str, &str)],
+        block: &[u8],
+    ) -> eyre::Result<()> {
+        let mut record = format!("{WARC_VERSION}\r\n");
+        let id = format!("<urn:uuid:{}>", uuid::Uuid::new_v4());
+        let date = date
+            .with_timezone(&Utc)
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+        for (name, value) in
+            [("WARC-Record-ID", id.as_str()), ("WARC-Date", &date)]
+                .iter()
+                .chain(fields)
+        {
+            record.push_str(&format!("{name}: {value}\r\n"));
+        }
+        record.push_str(&format!("Content-Length: {}\r\n\r\n", block.len()));
+
+        let mut record = record.into_bytes();
+        record.extend_from_slice(block);
+        record.extend_from_slice(b"\r\n\r\n");
+
+        if self.gzip {
+            let mut encoder =
+                GzEncoder::new(&mut self.out, Compression::default());
+            encoder.write_all(&record)?;
+            encoder.finish()?;
+        } else {
+            self.out.write_all(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Describe the file and what made it
+    pub fn write_info(&mut self) -> eyre::Result<()> {
+        let info = format!(
+            "software: {USER_AGENT}\r\nformat: WARC File Format 1.1\r\n"
+        );
+        self.write_record(
+            Utc::now().fixed_offset(),
+            &[
+                ("WARC-Type", "warcinfo"),
+                ("Content-Type", "application/warc-fields"),
+            ],
+            info.as_bytes(),
+        )
+    }
+
+    /// Record the HTML of the page at `url`, as captured at `fetched`
+    pub fn write_page(
+        &mut self,
+        url: &Url,
+        fetched: Timestamp,
+        html: &str,
+    ) -> eyre::Result<()> {
+        self.write_record(
+            fetched,
+            &[
+                ("WARC-Type", "resource"),
+                ("WARC-Target-URI", url.as_str()),
+                ("Content-Type", "text/html; charset=utf-8"),
+            ],
+            html.as_bytes(),
+        )
+    }
+
+    /// Flush everything written and return the underlying writer
+    pub fn finish(mut self) -> eyre::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let url = Url::parse("https://x.com/a").unwrap();
+        let fetched = parse_timestamp("2025-12-12T17:18:12+10:00").unwrap();
+
+        for gzip in [false, true] {
+            let mut writer = WarcWriter::new(Vec::new(), gzip);
+            writer.write_info().unwrap();
+            writer.write_page(&url, fetched, "<p>héllo</p>").unwrap();
+            writer.write_page(&url, fetched, "<p>again</p>").unwrap();
+            let bytes = writer.finish().unwrap();
+            assert_eq!(bytes.starts_with(&GZIP_MAGIC), gzip);
+
+            let pages = parse_pages(&bytes[..]).unwrap();
+            assert_eq!(pages.len(), 2);
+            assert_eq!(pages[0].url, url);
+            assert_eq!(pages[0].date, Some(fetched));
+            assert_eq!(pages[0].html, "<p>héllo</p>");
+            assert_eq!(pages[1].html, "<p>again</p>");
+        }
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        fn record(kind: &str, uri: &str, block: &str) -> String {
+            format!(
+                "WARC/1.0\r\nWARC-Type: {kind}\r\nWARC-Target-URI: <{uri}>\r\nWARC-Date: 2024-06-01T00:00:00Z\r\nContent-Length: {}\r\n\r\n{block}\r\n\r\n",
+                block.len()
+            )
+        }
+
+        let warc = [
+            record(
+                "request",
+                "https://x.com/a",
+                "GET /a HTTP/1.1\r\nHost: x.com\r\n\r\n",
+            ),
+            record(
+                "response",
+                "https://x.com/a",
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html;\r\n charset=utf-8\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n<p>hi\r\n4\r\n</p>\r\n0\r\n\r\n",
+            ),
+            record(
+                "response",
+                "https://x.com/gone",
+                "HTTP/1.1 404 Not Found\r\nContent-Type: text/html\r\n\r\n<p>gone</p>",
+            ),
+            record(
+                "response",
+                "https://x.com/style.css",
+                "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\n\r\np {}",
+            ),
+        ]
+        .concat();
+
+        let pages = parse_pages(warc.as_bytes()).unwrap();
+        assert_eq!(
+            pages,
+            vec![WarcPage {
+                url: Url::parse("https://x.com/a").unwrap(),
+                date: parse_timestamp("2024-06-01T00:00:00Z"),
+                html: "<p>hi</p>".to_string(),
+            }]
+        );
+
+        assert!(parse_pages(&b"<html></html>"[..]).is_err());
+        assert!(is_warc(Path::new("crawl.warc.gz")));
+        assert!(!is_warc(Path::new("bookmarks.md")));
+    }
+}